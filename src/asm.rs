@@ -0,0 +1,465 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::cpu::{RAM_SIZE, START_RAM_ADDRESS};
+use crate::error::{Error, Result};
+use crate::instruction::Instruction;
+
+// Nested includes deeper than this are assumed to be a cycle
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+// A source line, after includes have been expanded
+struct Line {
+    path: String,
+    number: usize,
+    text: String,
+}
+
+impl Line {
+    fn error(&self, message: impl Into<String>) -> Error {
+        Error::Assembly { path: self.path.clone(), line: self.number, message: message.into() }
+    }
+}
+
+enum Statement<'a> {
+    Instruction { mnemonic: String, operands: Vec<&'a str> },
+    Bytes(Vec<&'a str>),
+    Words(Vec<&'a str>),
+}
+
+struct Item<'a> {
+    line: &'a Line,
+    statement: Statement<'a>,
+}
+
+enum Symbol<'a> {
+    Address(u16),
+    Constant { value: &'a str, line: &'a Line },
+}
+
+// Assembles Cowgod style source into a ROM loaded at 0x200. Includes are
+// resolved relative to the directory of `path`.
+pub fn assemble(source: &str, path: &str) -> Result<Vec<u8>> {
+    let mut lines = Vec::new();
+    expand(source, path, 0, &mut lines)?;
+    Assembler::default().run(&lines)
+}
+
+pub fn assemble_file(path: &str) -> Result<Vec<u8>> {
+    let source = std::fs::read_to_string(path)?;
+    assemble(&source, path)
+}
+
+fn expand(source: &str, path: &str, depth: usize, lines: &mut Vec<Line>) -> Result<()> {
+    for (index, text) in source.lines().enumerate() {
+        let line = Line { path: path.to_string(), number: index + 1, text: strip_comment(text).trim().to_string() };
+        let mut words = line.text.splitn(2, char::is_whitespace);
+        if !words.next().is_some_and(|word| word.eq_ignore_ascii_case("include")) {
+            lines.push(line);
+            continue;
+        }
+        let name = words.next().unwrap_or("").trim();
+        let Some(name) = name.strip_prefix('"').and_then(|name| name.strip_suffix('"')) else {
+            return Err(line.error("INCLUDE expects a quoted file name"));
+        };
+        if depth >= MAX_INCLUDE_DEPTH {
+            return Err(line.error(format!("includes nested too deeply at {}", name)));
+        }
+        let included: PathBuf = Path::new(path).parent().unwrap_or(Path::new("")).join(name);
+        let source = std::fs::read_to_string(&included)
+            .map_err(|error| line.error(format!("cannot include {}: {}", included.display(), error)))?;
+        expand(&source, &included.to_string_lossy(), depth + 1, lines)?;
+    }
+    Ok(())
+}
+
+// Drops a `;` comment, leaving semicolons inside strings alone
+fn strip_comment(text: &str) -> &str {
+    let mut quoted = false;
+    for (position, character) in text.char_indices() {
+        match character {
+            '"' => quoted = !quoted,
+            ';' if !quoted => return &text[..position],
+            _ => (),
+        }
+    }
+    text
+}
+
+// Splits operands on commas outside strings
+fn split_operands(text: &str) -> Vec<&str> {
+    if text.trim().is_empty() {
+        return Vec::new();
+    }
+    let mut operands = Vec::new();
+    let (mut quoted, mut start) = (false, 0);
+    for (position, character) in text.char_indices() {
+        match character {
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                operands.push(text[start..position].trim());
+                start = position + 1;
+            },
+            _ => (),
+        }
+    }
+    operands.push(text[start..].trim());
+    operands
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut characters = text.chars();
+    characters.next().is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+        && characters.all(|character| character.is_ascii_alphanumeric() || character == '_' || character == '.')
+        && parse_register(text).is_none()
+}
+
+fn parse_register(text: &str) -> Option<u8> {
+    let digit = text.strip_prefix('V').or_else(|| text.strip_prefix('v'))?;
+    if digit.len() != 1 {
+        return None;
+    }
+    u8::from_str_radix(digit, 16).ok()
+}
+
+fn number(text: &str) -> Option<i64> {
+    let lower = text.to_ascii_lowercase();
+    if let Some(hex) = lower.strip_prefix("0x").or_else(|| lower.strip_prefix('#')) {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = lower.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()
+    } else {
+        lower.parse().ok()
+    }
+}
+
+#[derive(Default)]
+struct Assembler<'a> {
+    symbols: HashMap<String, Symbol<'a>>,
+}
+
+impl<'a> Assembler<'a> {
+    fn run(mut self, lines: &'a [Line]) -> Result<Vec<u8>> {
+        // First pass: sizes and label addresses
+        let mut items = Vec::new();
+        let mut address = START_RAM_ADDRESS;
+        for line in lines {
+            let mut text = line.text.as_str();
+            if let Some((label, rest)) = text.split_once(':') {
+                let label = label.trim();
+                if !label.contains('"') && !label.contains(char::is_whitespace) {
+                    if !is_identifier(label) {
+                        return Err(line.error(format!("invalid label name {}", label)));
+                    }
+                    self.define(label, Symbol::Address(address as u16), line)?;
+                    text = rest.trim();
+                }
+            }
+            if text.is_empty() {
+                continue;
+            }
+            let (mnemonic, rest) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+            let rest = rest.trim();
+            if let Some((keyword, value)) = rest.split_once(char::is_whitespace) {
+                if keyword.eq_ignore_ascii_case("equ") {
+                    if !is_identifier(mnemonic) {
+                        return Err(line.error(format!("invalid constant name {}", mnemonic)));
+                    }
+                    self.define(mnemonic, Symbol::Constant { value: value.trim(), line }, line)?;
+                    continue;
+                }
+            }
+            let operands = split_operands(rest);
+            let mnemonic = mnemonic.to_ascii_uppercase();
+            let (statement, size) = match mnemonic.as_str() {
+                "DB" => {
+                    let size = operands.iter().map(|operand| string(operand).map_or(1, str::len)).sum();
+                    (Statement::Bytes(operands), size)
+                },
+                "DW" => {
+                    let size = operands.len() * 2;
+                    (Statement::Words(operands), size)
+                },
+                _ => {
+                    let long = operands.get(1).is_some_and(|operand| operand.to_ascii_uppercase().starts_with("LONG "));
+                    (Statement::Instruction { mnemonic, operands }, if long { 4 } else { 2 })
+                },
+            };
+            items.push(Item { line, statement });
+            address += size;
+            if address > RAM_SIZE {
+                return Err(Error::RomTooLarge { size: address - START_RAM_ADDRESS, max: RAM_SIZE - START_RAM_ADDRESS });
+            }
+        }
+
+        // Second pass: encoding, now that every label is known
+        let mut rom = Vec::new();
+        for item in &items {
+            let line = item.line;
+            match &item.statement {
+                Statement::Bytes(operands) => {
+                    for operand in operands {
+                        match string(operand) {
+                            Some(text) => rom.extend_from_slice(text.as_bytes()),
+                            None => rom.push(self.value(operand, 8, line)? as u8),
+                        }
+                    }
+                },
+                Statement::Words(operands) => {
+                    for operand in operands {
+                        rom.extend_from_slice(&self.value(operand, 16, line)?.to_be_bytes());
+                    }
+                },
+                Statement::Instruction { mnemonic, operands } => {
+                    let instruction = self.instruction(mnemonic, operands, line)?;
+                    for word in instruction.encode() {
+                        rom.extend_from_slice(&word.to_be_bytes());
+                    }
+                },
+            }
+        }
+        Ok(rom)
+    }
+
+    fn define(&mut self, name: &str, symbol: Symbol<'a>, line: &Line) -> Result<()> {
+        if self.symbols.insert(name.to_string(), symbol).is_some() {
+            return Err(line.error(format!("{} is defined twice", name)));
+        }
+        Ok(())
+    }
+
+    // Evaluates a sum of numbers, labels and constants that must fit in
+    // `bits`; negative values are stored in two's complement
+    fn value(&self, text: &str, bits: u32, line: &Line) -> Result<u16> {
+        let value = self.evaluate(text, line, 0)?;
+        let max = (1i64 << bits) - 1;
+        if value > max || value < -(1i64 << (bits - 1)) {
+            return Err(line.error(format!("{} ({}) does not fit in {} bits", text, value, bits)));
+        }
+        Ok((value & max) as u16)
+    }
+
+    fn evaluate(&self, text: &str, line: &Line, depth: usize) -> Result<i64> {
+        if depth > self.symbols.len() {
+            return Err(line.error(format!("{} is defined in terms of itself", text)));
+        }
+        let text = text.trim();
+        if text.is_empty() {
+            return Err(line.error("missing value"));
+        }
+        // Split at the last + or - that isn't a sign, evaluating left to right
+        let split = text
+            .char_indices()
+            .rev()
+            .find(|&(position, character)| {
+                (character == '+' || character == '-') && position > 0 && !text[..position].trim_end().ends_with(['+', '-'])
+            })
+            .map(|(position, _)| position);
+        if let Some(position) = split {
+            let left = self.evaluate(&text[..position], line, depth)?;
+            let right = self.evaluate(&text[position + 1..], line, depth)?;
+            return Ok(if text[position..].starts_with('+') { left + right } else { left - right });
+        }
+        if let Some(negated) = text.strip_prefix('-') {
+            return Ok(-self.evaluate(negated, line, depth)?);
+        }
+        if let Some(value) = number(text) {
+            return Ok(value);
+        }
+        match self.symbols.get(text) {
+            Some(Symbol::Address(address)) => Ok(*address as i64),
+            Some(Symbol::Constant { value, line }) => self.evaluate(value, line, depth + 1),
+            None if is_identifier(text) => Err(line.error(format!("undefined symbol {}", text))),
+            None => Err(line.error(format!("invalid value {}", text))),
+        }
+    }
+
+    fn instruction(&self, mnemonic: &str, operands: &[&str], line: &Line) -> Result<Instruction> {
+        use Instruction::*;
+        let upper: Vec<String> = operands.iter().map(|operand| operand.to_ascii_uppercase()).collect();
+        let upper: Vec<&str> = upper.iter().map(String::as_str).collect();
+        let register = |index: usize| parse_register(operands[index]).ok_or_else(|| line.error(format!("expected a register, got {}", operands[index])));
+        let byte = |index: usize| self.value(operands[index], 8, line).map(|value| value as u8);
+        let nibble = |index: usize| self.value(operands[index], 4, line).map(|value| value as u8);
+        let address = |text: &str| self.value(text, 12, line);
+        let instruction = match (mnemonic, upper.as_slice()) {
+            ("CLS", []) => Clear,
+            ("RET", []) => Return,
+            ("SCR", []) => ScrollRight,
+            ("SCL", []) => ScrollLeft,
+            ("EXIT", []) => Exit,
+            ("LOW", []) => Lores,
+            ("HIGH", []) => Hires,
+            ("AUDIO", []) => Audio,
+            ("SCD", [_]) => ScrollDown(nibble(0)?),
+            ("SCU", [_]) => ScrollUp(nibble(0)?),
+            ("JP", ["V0", _]) => JumpOffset(address(operands[1])?),
+            ("JP", [_]) => Jump(address(operands[0])?),
+            ("CALL", [_]) => Call(address(operands[0])?),
+            ("SE", [_, y]) if parse_register(y).is_some() => SkipIfRegistersEqual(register(0)?, register(1)?),
+            ("SE", [_, _]) => SkipIfEqual(register(0)?, byte(1)?),
+            ("SNE", [_, y]) if parse_register(y).is_some() => SkipIfRegistersNotEqual(register(0)?, register(1)?),
+            ("SNE", [_, _]) => SkipIfNotEqual(register(0)?, byte(1)?),
+            ("SAVE" | "LOAD", [range]) => {
+                let Some((x, y)) = range.split_once('-') else {
+                    return Err(line.error(format!("{} expects a register range like V1 - V3", mnemonic)));
+                };
+                let (x, y) = (parse_register(x.trim()), parse_register(y.trim()));
+                let (Some(x), Some(y)) = (x, y) else {
+                    return Err(line.error(format!("invalid register range {}", operands[0])));
+                };
+                if mnemonic == "SAVE" { SaveRange(x, y) } else { LoadRange(x, y) }
+            },
+            ("LD", ["I", long]) if long.starts_with("LONG ") => SetIndexLong(self.value(&operands[1][5..], 16, line)?),
+            ("LD", ["I", _]) => SetIndex(address(operands[1])?),
+            ("LD", ["DT", _]) => SetDelay(register(1)?),
+            ("LD", ["ST", _]) => SetSound(register(1)?),
+            ("LD", ["F", _]) => Font(register(1)?),
+            ("LD", ["HF", _]) => BigFont(register(1)?),
+            ("LD", ["B", _]) => Bcd(register(1)?),
+            ("LD", ["[I]", _]) => Store(register(1)?),
+            ("LD", ["R", _]) => SaveFlags(register(1)?),
+            ("LD", [_, "DT"]) => GetDelay(register(0)?),
+            ("LD", [_, "K"]) => WaitKey(register(0)?),
+            ("LD", [_, "[I]"]) => Load(register(0)?),
+            ("LD", [_, "R"]) => LoadFlags(register(0)?),
+            ("LD", [_, y]) if parse_register(y).is_some() => Copy(register(0)?, register(1)?),
+            ("LD", [_, _]) => Set(register(0)?, byte(1)?),
+            ("ADD", ["I", _]) => AddIndex(register(1)?),
+            ("ADD", [_, y]) if parse_register(y).is_some() => AddRegister(register(0)?, register(1)?),
+            ("ADD", [_, _]) => Add(register(0)?, byte(1)?),
+            ("OR", [_, _]) => Or(register(0)?, register(1)?),
+            ("AND", [_, _]) => And(register(0)?, register(1)?),
+            ("XOR", [_, _]) => Xor(register(0)?, register(1)?),
+            ("SUB", [_, _]) => Subtract(register(0)?, register(1)?),
+            ("SUBN", [_, _]) => SubtractReversed(register(0)?, register(1)?),
+            // VY defaults to VX, which reads the same under either shift quirk
+            ("SHR", [_]) => ShiftRight(register(0)?, register(0)?),
+            ("SHR", [_, _]) => ShiftRight(register(0)?, register(1)?),
+            ("SHL", [_]) => ShiftLeft(register(0)?, register(0)?),
+            ("SHL", [_, _]) => ShiftLeft(register(0)?, register(1)?),
+            ("RND", [_, _]) => Random(register(0)?, byte(1)?),
+            ("DRW", [_, _, _]) => Draw(register(0)?, register(1)?, nibble(2)?),
+            ("SKP", [_]) => SkipIfKey(register(0)?),
+            ("SKNP", [_]) => SkipIfNotKey(register(0)?),
+            ("PLANE", [_]) => Plane(nibble(0)?),
+            ("PITCH", [_]) => Pitch(register(0)?),
+            _ => {
+                let message = if known(mnemonic) {
+                    format!("invalid operands for {}: {}", mnemonic, operands.join(", "))
+                } else {
+                    format!("unknown instruction {}", mnemonic)
+                };
+                return Err(line.error(message));
+            },
+        };
+        Ok(instruction)
+    }
+}
+
+fn known(mnemonic: &str) -> bool {
+    const MNEMONICS: [&str; 31] = [
+        "CLS", "RET", "SCR", "SCL", "EXIT", "LOW", "HIGH", "AUDIO", "SCD", "SCU", "JP", "CALL", "SE", "SNE", "SAVE", "LOAD", "LD", "ADD",
+        "OR", "AND", "XOR", "SUB", "SUBN", "SHR", "SHL", "RND", "DRW", "SKP", "SKNP", "PLANE", "PITCH",
+    ];
+    MNEMONICS.contains(&mnemonic)
+}
+
+fn string(operand: &str) -> Option<&str> {
+    operand.strip_prefix('"')?.strip_suffix('"')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disasm::{self, Syntax};
+
+    fn words(rom: &[u8]) -> Vec<u16> {
+        rom.chunks(2).map(|pair| u16::from_be_bytes([pair[0], pair[1]])).collect()
+    }
+
+    // The message and line of a failed assembly
+    fn error(source: &str) -> (usize, String) {
+        match assemble(source, "test.asm") {
+            Err(Error::Assembly { line, message, .. }) => (line, message),
+            other => panic!("expected an assembly error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn labels_and_forward_references() {
+        let source = "
+            start:  CALL draw     ; defined further down
+                    JP start
+            draw:   LD I, sprite
+                    RET
+            sprite: DB 0xFF
+        ";
+        assert_eq!(assemble(source, "test.asm").unwrap(), [0x22, 0x04, 0x12, 0x00, 0xA2, 0x08, 0x00, 0xEE, 0xFF]);
+    }
+
+    #[test]
+    fn constants() {
+        let source = "
+            SPEED equ 3
+            LIMIT equ SPEED + SPEED - 1
+            TABLE equ data + 2
+                LD V1, LIMIT
+                ADD V1, -SPEED
+                LD I, TABLE
+            data: DB 1, 2, 3
+        ";
+        assert_eq!(words(&assemble(source, "test.asm").unwrap()[..6]), [0x6105, 0x71FD, 0xA208]);
+        assert_eq!(error("A equ B\nB equ A\nLD V0, A"), (1, "B is defined in terms of itself".to_string()));
+    }
+
+    #[test]
+    fn data() {
+        let source = "
+            DB 0x12, 255, -1, 0b1010, \"Hi\"
+            DW 0x1234, end
+            end:
+        ";
+        assert_eq!(assemble(source, "test.asm").unwrap(), [0x12, 0xFF, 0xFF, 0x0A, b'H', b'i', 0x12, 0x34, 0x02, 0x0A]);
+        assert_eq!(error("DB 256"), (1, "256 (256) does not fit in 8 bits".to_string()));
+    }
+
+    #[test]
+    fn includes() {
+        let directory = std::env::temp_dir().join(format!("chip8-asm-includes-{}", std::process::id()));
+        std::fs::create_dir_all(directory.join("lib")).unwrap();
+        std::fs::write(directory.join("main.asm"), "include \"lib/sprites.asm\"\n    LD I, heart\n").unwrap();
+        std::fs::write(directory.join("lib/sprites.asm"), "HEART_ROWS equ 2\nheart: DB 0x66, 0xFF\n").unwrap();
+        std::fs::write(directory.join("loop.asm"), "include \"loop.asm\"\n").unwrap();
+
+        let rom = assemble_file(directory.join("main.asm").to_str().unwrap());
+        let looping = assemble_file(directory.join("loop.asm").to_str().unwrap());
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(rom.unwrap(), [0x66, 0xFF, 0xA2, 0x00]);
+        assert!(matches!(looping, Err(Error::Assembly { line: 1, ref message, .. }) if message.starts_with("includes nested too deeply")));
+    }
+
+    #[test]
+    fn errors_report_their_line() {
+        assert_eq!(error("CLS\n\n    JP nowhere\n"), (3, "undefined symbol nowhere".to_string()));
+        assert_eq!(error("CLS\nFOO V1"), (2, "unknown instruction FOO".to_string()));
+        assert_eq!(error("ADD V1, V2, V3"), (1, "invalid operands for ADD: V1, V2, V3".to_string()));
+        assert_eq!(error("loop: CLS\nloop: RET"), (2, "loop is defined twice".to_string()));
+        let message = assemble("\nLD VG, 1", "game.asm").unwrap_err().to_string();
+        assert_eq!(message, "game.asm:2: expected a register, got VG");
+    }
+
+    #[test]
+    fn disassembly_reassembles_to_the_bundled_roms() {
+        let directory = concat!(env!("CARGO_MANIFEST_DIR"), "/rom");
+        let mut roms: Vec<_> = std::fs::read_dir(directory).unwrap().map(|entry| entry.unwrap().path()).collect();
+        roms.sort();
+        assert!(!roms.is_empty());
+        for path in roms {
+            let rom = std::fs::read(&path).unwrap();
+            let listing = disasm::listing(&rom, Syntax::Cowgod);
+            let reassembled = assemble(&listing, "listing.asm").unwrap_or_else(|error| panic!("{}: {}", path.display(), error));
+            assert!(reassembled == rom, "{} does not reassemble to itself", path.display());
+        }
+    }
+}
@@ -0,0 +1,46 @@
+use chip8_emulator::{asm, error::{Error, Result}};
+
+const USAGE: &str = "\
+Usage: chip8-asm [OPTIONS] <SOURCE>
+
+Assembles Cowgod style CHIP-8 source into a ROM loaded at 0x200.
+
+Options:
+  -o, --output <FILE>    Where to write the ROM [default: SOURCE with a .ch8
+                         extension]
+  -h, --help             Print this help
+";
+
+fn main() {
+    if let Err(error) = run() {
+        eprintln!("error: {}", error);
+        if let Error::InvalidArgument(_) = error {
+            eprintln!("Run with --help for usage");
+        }
+        std::process::exit(1);
+    }
+}
+
+fn run() -> Result<()> {
+    let mut args = std::env::args().skip(1);
+    let mut output = None;
+    let mut source = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                print!("{}", USAGE);
+                return Ok(());
+            },
+            "-o" | "--output" => output = Some(args.next().ok_or_else(|| Error::InvalidArgument(format!("{} expects a value", arg)))?),
+            _ if arg.starts_with('-') => return Err(Error::InvalidArgument(format!("unknown option {}", arg))),
+            _ if source.is_none() => source = Some(arg),
+            _ => return Err(Error::InvalidArgument(format!("unexpected argument {}", arg))),
+        }
+    }
+    let source = source.ok_or_else(|| Error::InvalidArgument("missing source path".to_string()))?;
+    let rom = asm::assemble_file(&source)?;
+    let output = output.unwrap_or_else(|| std::path::Path::new(&source).with_extension("ch8").to_string_lossy().into_owned());
+    std::fs::write(&output, &rom)?;
+    eprintln!("Assembled {} bytes to {}", rom.len(), output);
+    Ok(())
+}
@@ -0,0 +1,54 @@
+use chip8_emulator::{disasm::{self, Syntax}, error::{Error, Result}};
+
+const USAGE: &str = "\
+Usage: chip8-disasm [OPTIONS] <ROM>
+
+Disassembles a ROM loaded at 0x200, following jumps and calls to tell code
+from data.
+
+Options:
+  -s, --syntax <NAME>    Mnemonics: cowgod or octo [default: cowgod]
+  -o, --output <FILE>    Write the listing to FILE instead of stdout
+  -h, --help             Print this help
+";
+
+fn main() {
+    if let Err(error) = run() {
+        eprintln!("error: {}", error);
+        if let Error::InvalidArgument(_) = error {
+            eprintln!("Run with --help for usage");
+        }
+        std::process::exit(1);
+    }
+}
+
+fn run() -> Result<()> {
+    let mut args = std::env::args().skip(1);
+    let mut syntax = Syntax::default();
+    let mut output = None;
+    let mut rom = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                print!("{}", USAGE);
+                return Ok(());
+            },
+            "-s" | "--syntax" => {
+                let name = args.next().ok_or_else(|| Error::InvalidArgument(format!("{} expects a value", arg)))?;
+                syntax = Syntax::from_name(&name).ok_or_else(|| Error::InvalidArgument(format!("unknown syntax {}", name)))?;
+            },
+            "-o" | "--output" => output = Some(args.next().ok_or_else(|| Error::InvalidArgument(format!("{} expects a value", arg)))?),
+            _ if arg.starts_with('-') => return Err(Error::InvalidArgument(format!("unknown option {}", arg))),
+            _ if rom.is_none() => rom = Some(arg),
+            _ => return Err(Error::InvalidArgument(format!("unexpected argument {}", arg))),
+        }
+    }
+    let path = rom.ok_or_else(|| Error::InvalidArgument("missing ROM path".to_string()))?;
+    let bytes = std::fs::read(&path).map_err(|_| Error::RomNotFound { path: path.clone() })?;
+    let listing = disasm::listing(&bytes, syntax);
+    match output {
+        Some(file) => std::fs::write(file, listing)?,
+        None => print!("{}", listing),
+    }
+    Ok(())
+}
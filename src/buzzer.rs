@@ -0,0 +1,177 @@
+use std::f32::consts::TAU;
+
+pub const SAMPLE_RATE: u32 = 44100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Waveform {
+    #[default]
+    Square,
+    Triangle,
+    Sine,
+    // A random level held for each period, so the frequency sets its color
+    Noise,
+}
+
+impl Waveform {
+    pub const ALL: [Waveform; 4] = [Waveform::Square, Waveform::Triangle, Waveform::Sine, Waveform::Noise];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|waveform| waveform.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Waveform::Square => "square",
+            Waveform::Triangle => "triangle",
+            Waveform::Sine => "sine",
+            Waveform::Noise => "noise",
+        }
+    }
+}
+
+// How the buzzer sounds. XO-CHIP pattern audio replaces the waveform and
+// frequency but keeps the volume and envelope.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tone {
+    // Hz
+    pub frequency: f32,
+    pub waveform: Waveform,
+    // 0 to 1
+    pub volume: f32,
+    // Seconds the volume takes to rise once the sound starts and to fall
+    // once it stops, which keeps the edges from clicking
+    pub attack: f32,
+    pub release: f32,
+}
+
+impl Default for Tone {
+    fn default() -> Self {
+        Self {
+            frequency: 440.0,
+            waveform: Waveform::Square,
+            volume: 0.25,
+            attack: 0.002,
+            release: 0.005,
+        }
+    }
+}
+
+// Samples of the sound a program makes while the sound timer runs: the tone,
+// or the XO-CHIP pattern buffer once one was loaded. Shared by the live
+// output and the WAV recorder so both sound the same.
+//
+// The oscillators keep running while the gate is closed, so a sound that
+// stops and starts again picks up its wave where it would have been instead
+// of restarting it.
+pub struct Buzzer {
+    sample_rate: f32,
+    tone: Tone,
+    phase_inc: f32,
+    phase: f32,
+    // XO-CHIP 128-bit pattern, played instead of the tone once loaded
+    pattern: Option<[u8; 16]>,
+    pattern_inc: f32,
+    pattern_phase: f32,
+    gate: bool,
+    // Envelope, 0 to 1
+    level: f32,
+    attack_step: f32,
+    release_step: f32,
+    // Xorshift state and the level it gave for the current noise period
+    noise: u32,
+    noise_level: f32,
+}
+
+// Playback rate of the pattern buffer in bits per second
+fn pattern_rate(pitch: u8) -> f32 {
+    4000.0 * 2f32.powf((pitch as f32 - 64.0) / 48.0)
+}
+
+// Envelope change per sample for a ramp of `seconds`, instant when zero
+fn ramp_step(seconds: f32, sample_rate: f32) -> f32 {
+    if seconds > 0.0 {
+        1.0 / (seconds * sample_rate)
+    } else {
+        1.0
+    }
+}
+
+impl Buzzer {
+    pub fn new(sample_rate: u32, tone: Tone) -> Self {
+        let sample_rate = sample_rate as f32;
+        Self {
+            sample_rate,
+            tone,
+            phase_inc: tone.frequency / sample_rate,
+            phase: 0.0,
+            pattern: None,
+            pattern_inc: pattern_rate(64) / sample_rate,
+            pattern_phase: 0.0,
+            gate: false,
+            level: 0.0,
+            attack_step: ramp_step(tone.attack, sample_rate),
+            release_step: ramp_step(tone.release, sample_rate),
+            noise: 0x2545_F491,
+            noise_level: 0.0,
+        }
+    }
+
+    // Opens or closes the envelope; the sound fades in or out from wherever
+    // it is rather than jumping
+    pub fn set_gate(&mut self, gate: bool) {
+        self.gate = gate;
+    }
+
+    pub fn set_pattern(&mut self, pattern: Option<[u8; 16]>, pitch: u8) {
+        self.pattern = pattern;
+        self.pattern_inc = pattern_rate(pitch) / self.sample_rate;
+    }
+
+    pub fn fill(&mut self, out: &mut [f32]) {
+        for x in out.iter_mut() {
+            self.level = if self.gate {
+                (self.level + self.attack_step).min(1.0)
+            } else {
+                (self.level - self.release_step).max(0.0)
+            };
+            let wave = self.tone_sample();
+            let sample = match self.pattern {
+                Some(pattern) => self.pattern_sample(pattern),
+                None => wave,
+            };
+            *x = sample * self.level * self.tone.volume;
+        }
+    }
+
+    fn tone_sample(&mut self) -> f32 {
+        let phase = self.phase;
+        self.phase += self.phase_inc;
+        if self.phase >= 1.0 {
+            self.phase %= 1.0;
+            self.next_noise();
+        }
+        match self.tone.waveform {
+            Waveform::Square => if phase <= 0.5 { 1.0 } else { -1.0 },
+            Waveform::Triangle => 4.0 * (phase - 0.5).abs() - 1.0,
+            Waveform::Sine => (phase * TAU).sin(),
+            Waveform::Noise => self.noise_level,
+        }
+    }
+
+    fn pattern_sample(&mut self, pattern: [u8; 16]) -> f32 {
+        let bit = self.pattern_phase as usize;
+        self.pattern_phase = (self.pattern_phase + self.pattern_inc) % 128.0;
+        if pattern[bit / 8] & (0x80 >> (bit % 8)) != 0 {
+            1.0
+        } else {
+            -1.0
+        }
+    }
+
+    fn next_noise(&mut self) {
+        self.noise ^= self.noise << 13;
+        self.noise ^= self.noise >> 17;
+        self.noise ^= self.noise << 5;
+        self.noise_level = self.noise as f32 / u32::MAX as f32 * 2.0 - 1.0;
+    }
+}
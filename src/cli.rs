@@ -0,0 +1,254 @@
+use crate::buzzer::{Tone, Waveform};
+use crate::emulator::ClockMode;
+use crate::error::{Error, Result};
+use crate::palette::Palette;
+use crate::quirks::Variant;
+use crate::trace::TraceFilter;
+use crate::watch::{WatchAction, Watchpoint};
+
+pub const USAGE: &str = "\
+Usage: chip8-emulator [OPTIONS] <ROM>
+
+A ROM ending in .8o is Octo source, compiled when loaded.
+
+Options:
+  -i, --ipf <N>          Instructions executed per 60 Hz frame [default: 15]
+  -s, --scale <N>        Window pixels per CHIP-8 pixel [default: 10]
+  -v, --variant <NAME>   Quirks preset: modern (every quirk off), vip, schip,
+                         xochip, octo [default: modern]
+      --seed <N>         Seed for CXNN random numbers [default: random]
+      --clock <MODE>     Timer clock: wall (host time) or emulated (one tick
+                         per frame, deterministic) [default: wall]
+  -c, --colors <LIST>    Comma separated RRGGBB colors: background,foreground
+                         or background,plane1,plane2,both
+  -r, --rewind <SECS>    Seconds of history kept for rewinding [default: 10]
+  -m, --mute             Disable sound
+      --tone <HZ>        Buzzer frequency [default: 440]
+      --waveform <SHAPE> Buzzer waveform: square, triangle, sine or noise
+                         [default: square]
+      --volume <LEVEL>   Sound volume from 0 to 1 [default: 0.25]
+      --attack <MS>      Milliseconds the sound takes to fade in [default: 2]
+      --release <MS>     Milliseconds the sound takes to fade out [default: 5]
+      --wav <FILE>       Record the sound into a WAV file, 1/60 s per
+                         emulated frame, saved when the emulator exits
+  -p, --paused           Start paused (toggle with P)
+  -d, --debug            Start paused in the terminal debugger (type help
+                         there for its commands)
+      --watch <RANGE>    Log memory accessed through I in RANGE, written
+                         START[-END][:r|w|rw], or program[:r|w|rw] to watch
+                         the ROM's bytes for self-modifying code; repeatable
+      --record <FILE>    Record the keypad into a movie file (uses the
+                         emulated clock)
+      --play <FILE>      Play back a movie file; its quirks, seed and
+                         instructions per frame replace the options above
+      --headless         Run without a window as fast as possible on the
+                         emulated clock, then print the frame count and
+                         framebuffer hash
+      --frames <N>       Stop a headless run after N frames
+      --screenshot <FILE>
+                         Save the screen as a PNG when the emulator exits
+      --clip <FILE>      Record the screen into an animated PNG, at 60
+                         frames per second, saved when the emulator exits
+      --screenshot-scale <N>
+                         Image pixels per CHIP-8 pixel in screenshots and
+                         clips [default: 1]
+      --trace <FILE>     Write every executed instruction with the registers
+                         and I before and after it to FILE
+      --trace-range <RANGE>
+                         Only trace instructions at START[-END]; repeatable
+      --trace-ops <LIST> Only trace these comma separated mnemonics, e.g.
+                         DRW,CALL,RET
+  -h, --help             Print this help
+
+Keys:
+  P                      Pause / resume
+  Backspace              Rewind (hold)
+  F1-F9                  Load save state slot 1-9
+  Shift+F1-F9            Save state slot 1-9 (stored as <ROM>.state<N>)
+  F11                    Start / stop recording a clip (<ROM>.clip<N>.png)
+  F12                    Save a screenshot as <ROM>.<N>.png
+  Escape                 Quit
+";
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub rom: String,
+    pub instructions_per_frame: u32,
+    pub scale: u32,
+    pub variant: Variant,
+    pub clock: ClockMode,
+    pub seed: Option<u64>,
+    pub rewind_seconds: u32,
+    pub palette: Palette,
+    pub mute: bool,
+    pub tone: Tone,
+    pub wav: Option<String>,
+    pub paused: bool,
+    pub debug: bool,
+    pub watchpoints: Vec<Watchpoint>,
+    pub record: Option<String>,
+    pub play: Option<String>,
+    pub headless: bool,
+    pub frames: Option<u64>,
+    pub screenshot: Option<String>,
+    pub screenshot_scale: u32,
+    pub clip: Option<String>,
+    pub trace: Option<String>,
+    pub trace_filter: TraceFilter,
+}
+
+#[derive(Debug)]
+pub enum Parsed {
+    Run(Box<Options>),
+    Help,
+}
+
+impl Options {
+    pub fn new(rom: String) -> Self {
+        Self {
+            rom,
+            instructions_per_frame: 15,
+            scale: 10,
+            variant: Variant::default(),
+            clock: ClockMode::default(),
+            seed: None,
+            rewind_seconds: 10,
+            palette: Palette::default(),
+            mute: false,
+            tone: Tone::default(),
+            wav: None,
+            paused: false,
+            debug: false,
+            watchpoints: Vec::new(),
+            record: None,
+            play: None,
+            headless: false,
+            frames: None,
+            screenshot: None,
+            screenshot_scale: 1,
+            clip: None,
+            trace: None,
+            trace_filter: TraceFilter::default(),
+        }
+    }
+}
+
+fn invalid(message: String) -> Error {
+    Error::InvalidArgument(message)
+}
+
+fn value(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<String> {
+    args.next().ok_or_else(|| invalid(format!("{} expects a value", flag)))
+}
+
+fn number(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<u32> {
+    let text = value(args, flag)?;
+    match text.parse() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(invalid(format!("{} expects a positive number, got {}", flag, text))),
+    }
+}
+
+fn decimal(args: &mut impl Iterator<Item = String>, flag: &str, valid: impl Fn(f32) -> bool) -> Result<f32> {
+    let text = value(args, flag)?;
+    match text.parse() {
+        Ok(n) if valid(n) => Ok(n),
+        _ => Err(invalid(format!("invalid value {} for {}", text, flag))),
+    }
+}
+
+// Parses the arguments following the program name
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Parsed> {
+    let mut args = args.into_iter();
+    let mut rom = None;
+    let mut options = Options::new(String::new());
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Parsed::Help),
+            "-i" | "--ipf" => options.instructions_per_frame = number(&mut args, &arg)?,
+            "-s" | "--scale" => options.scale = number(&mut args, &arg)?,
+            "-v" | "--variant" => {
+                let name = value(&mut args, &arg)?;
+                options.variant = Variant::from_name(&name)
+                    .ok_or_else(|| invalid(format!("unknown variant {}", name)))?;
+            },
+            "--seed" => {
+                let text = value(&mut args, &arg)?;
+                options.seed = Some(text.parse().map_err(|_| invalid(format!("invalid seed {}", text)))?);
+            },
+            "--clock" => {
+                options.clock = match value(&mut args, &arg)?.as_str() {
+                    "wall" => ClockMode::WallClock,
+                    "emulated" => ClockMode::Emulated,
+                    other => return Err(invalid(format!("unknown clock mode {}", other))),
+                };
+            },
+            "-c" | "--colors" => {
+                let list = value(&mut args, &arg)?;
+                options.palette = Palette::parse(&list)
+                    .ok_or_else(|| invalid(format!("invalid color list {}", list)))?;
+            },
+            "-r" | "--rewind" => {
+                let text = value(&mut args, &arg)?;
+                options.rewind_seconds = text.parse().map_err(|_| invalid(format!("invalid rewind length {}", text)))?;
+            },
+            "-m" | "--mute" => options.mute = true,
+            "--tone" => options.tone.frequency = decimal(&mut args, &arg, |hz| hz > 0.0 && hz.is_finite())?,
+            "--waveform" => {
+                let name = value(&mut args, &arg)?;
+                options.tone.waveform = Waveform::from_name(&name)
+                    .ok_or_else(|| invalid(format!("unknown waveform {}", name)))?;
+            },
+            "--volume" => options.tone.volume = decimal(&mut args, &arg, |level| (0.0..=1.0).contains(&level))?,
+            "--attack" => options.tone.attack = decimal(&mut args, &arg, |ms| (0.0..=1000.0).contains(&ms))? / 1000.0,
+            "--release" => options.tone.release = decimal(&mut args, &arg, |ms| (0.0..=1000.0).contains(&ms))? / 1000.0,
+            "--wav" => options.wav = Some(value(&mut args, &arg)?),
+            "-p" | "--paused" => options.paused = true,
+            "-d" | "--debug" => options.debug = true,
+            "--watch" => {
+                let range = value(&mut args, &arg)?;
+                let watchpoint = Watchpoint::parse(&range, WatchAction::Log)
+                    .ok_or_else(|| invalid(format!("invalid watch range {}", range)))?;
+                options.watchpoints.push(watchpoint);
+            },
+            "--record" => options.record = Some(value(&mut args, &arg)?),
+            "--play" => options.play = Some(value(&mut args, &arg)?),
+            "--headless" => options.headless = true,
+            "--frames" => options.frames = Some(number(&mut args, &arg)? as u64),
+            "--screenshot" => options.screenshot = Some(value(&mut args, &arg)?),
+            "--clip" => options.clip = Some(value(&mut args, &arg)?),
+            "--screenshot-scale" => options.screenshot_scale = number(&mut args, &arg)?,
+            "--trace" => options.trace = Some(value(&mut args, &arg)?),
+            "--trace-range" => {
+                let range = value(&mut args, &arg)?;
+                let range = TraceFilter::parse_range(&range).ok_or_else(|| invalid(format!("invalid trace range {}", range)))?;
+                options.trace_filter.ranges.push(range);
+            },
+            "--trace-ops" => {
+                let list = value(&mut args, &arg)?;
+                options.trace_filter.mnemonics = TraceFilter::parse_mnemonics(&list)
+                    .ok_or_else(|| invalid(format!("invalid mnemonic list {}", list)))?;
+            },
+            _ if arg.starts_with('-') => return Err(invalid(format!("unknown option {}", arg))),
+            _ if rom.is_none() => rom = Some(arg),
+            _ => return Err(invalid(format!("unexpected argument {}", arg))),
+        }
+    }
+    options.rom = rom.ok_or_else(|| invalid("missing ROM path".to_string()))?;
+    if options.headless && options.play.is_none() && options.frames.is_none() {
+        return Err(invalid("--headless needs --play or --frames to know when to stop".to_string()));
+    }
+    // Instructions stepped in the debugger can't be replayed from keys
+    if options.record.is_some() && options.debug {
+        return Err(invalid("--record can't be combined with --debug".to_string()));
+    }
+    // A movie plays its own keys and the pause key isn't among them, so a
+    // paused playback could never be resumed
+    if options.play.is_some() && options.paused {
+        return Err(invalid("--paused can't be combined with --play".to_string()));
+    }
+    if options.trace.is_none() && options.trace_filter != TraceFilter::default() {
+        return Err(invalid("--trace-range and --trace-ops need --trace".to_string()));
+    }
+    Ok(Parsed::Run(Box::new(options)))
+}
@@ -0,0 +1,77 @@
+use crate::cpu::Framebuffer;
+use crate::error::{Error, Result};
+use crate::palette::Palette;
+use crate::screenshot::{self, encoding};
+
+// One screen, shown for `frames` 60 Hz frames
+struct Frame {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+    frames: u16,
+}
+
+// The screen at every frame, saved as an animated PNG with exact 1/60 s
+// frame delays. Frames are kept at their native resolution until encoding,
+// repeated screens being merged into longer frames.
+#[derive(Default)]
+pub struct Clip {
+    frames: Vec<Frame>,
+}
+
+impl Clip {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Adds one 60 Hz frame
+    pub fn push(&mut self, vram: &Framebuffer, width: usize, height: usize) {
+        let pixels = screenshot::visible(vram, width, height);
+        if let Some(last) = self.frames.last_mut() {
+            if last.width == width && last.pixels == pixels && last.frames < u16::MAX {
+                last.frames += 1;
+                return;
+            }
+        }
+        self.frames.push(Frame { width, height, pixels, frames: 1 });
+    }
+
+    // Length in 60 Hz frames
+    pub fn len(&self) -> u64 {
+        self.frames.iter().map(|frame| frame.frames as u64).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    // Lores frames of a clip that switched to hires are scaled up to fill the
+    // larger screen, and every pixel then becomes a `scale` x `scale` square
+    pub fn encode(&self, palette: &Palette, scale: u32) -> Result<Vec<u8>> {
+        if self.frames.is_empty() {
+            return Err(Error::InvalidArgument("the clip has no frames".to_string()));
+        }
+        let width = self.frames.iter().map(|frame| frame.width).max().unwrap_or(0);
+        let height = self.frames.iter().map(|frame| frame.height).max().unwrap_or(0);
+        let scale = scale.max(1) as usize;
+
+        let mut bytes = Vec::new();
+        let mut encoder = screenshot::encoder(&mut bytes, width * scale, height * scale, palette);
+        // Loops forever
+        encoder.set_animated(self.frames.len() as u32, 0).map_err(encoding)?;
+        let mut writer = encoder.write_header().map_err(encoding)?;
+        for frame in &self.frames {
+            writer.set_frame_delay(frame.frames, 60).map_err(encoding)?;
+            let pixels = screenshot::scaled(&frame.pixels, frame.width, width / frame.width * scale);
+            writer.write_image_data(&pixels).map_err(encoding)?;
+        }
+        writer.finish().map_err(encoding)?;
+        Ok(bytes)
+    }
+
+    pub fn save(&self, palette: &Palette, scale: u32, path: &str) -> Result<()> {
+        let bytes = self.encode(palette, scale)?;
+        std::fs::write(path, bytes)?;
+        Ok(())
+    }
+}
@@ -1,43 +1,77 @@
-use std::{io::Read, time::Instant};
+use std::io::Read;
 
-use crate::{emulator::{START_RAM_ADDRESS, FONT_OFFSET}, drivers::display::{WIDTH, HEIGHT, DisplayDriver}};
+use crate::{error::{Error, Result}, quirks::Quirks, rng::{RandomSource, XorShiftRng}, snapshot::{self, Reader, Writer}, watch::{Access, Watchpoints}, instruction::Instruction, disasm, trace::{self, Tracer}};
 
+// XO-CHIP's memory, the most any variant addresses
+pub const RAM_SIZE: usize = 0x10000;
+pub const CLASSIC_RAM_SIZE: usize = 0x1000;
+pub const START_RAM_ADDRESS: usize = 0x200;
+pub const FONT_OFFSET: usize = 0x00;
+pub const BIG_FONT_OFFSET: usize = 0x50;
+pub const STACK_SIZE: usize = 16;
+
+pub const LORES_WIDTH: u32 = 64;
+pub const LORES_HEIGHT: u32 = 32;
+pub const HIRES_WIDTH: u32 = 128;
+pub const HIRES_HEIGHT: u32 = 64;
+
+// One byte per pixel, holding a bit for each XO-CHIP plane
+pub type Framebuffer = [[u8; HIRES_WIDTH as usize]; HIRES_HEIGHT as usize];
+
+// 60 Hz countdown; the emulator decides when a tick happens
+#[derive(Default)]
 pub struct Timer {
     counter: u8,
-    last_update: Instant,
 }
 
-const TARGET_FPS: u128 = 60;
-const TARGET_FRAME_TIME_MICROS: u128 = 1_000_000 / TARGET_FPS;
-
 impl Timer {
-    pub fn update(&mut self) {
-        let now = Instant::now();
-        let elapsed = now.duration_since(self.last_update);
-        if elapsed.as_micros() >= TARGET_FRAME_TIME_MICROS {
-            if self.counter > 0 {
-                self.counter -= 1;
-            }
-            self.last_update = now;
-        }
-    } 
+    pub fn tick(&mut self) {
+        self.counter = self.counter.saturating_sub(1);
+    }
 
     pub fn is_zero(&self) -> bool {
         self.counter == 0
     }
+
+    pub fn value(&self) -> u8 {
+        self.counter
+    }
 }
 
 pub struct CPU {
     pc: u16,
     registers: [u8; 16],
     i: u16,
-    ram: [u8; 4096],
-    vram: [[bool; WIDTH as usize]; HEIGHT as usize],
+    ram: Vec<u8>,
+    vram: Framebuffer,
     vram_changed: bool,
-    stack: Vec<u8>,
-    pub timer: Timer,
-    pub sound_timer: Timer,
-    pub key_buffer: [bool; 16],
+    hires: bool,
+    plane: u8,
+    audio_pattern: Option<[u8; 16]>,
+    pitch: u8,
+    halted: bool,
+    stack: Vec<u16>,
+    rpl: [u8; 16],
+    timer: Timer,
+    sound_timer: Timer,
+    key_buffer: [bool; 16],
+    // Key pressed during FX0A, waiting for its release
+    key_wait: Option<u8>,
+    quirks: Quirks,
+    seed: u64,
+    rng: Box<dyn RandomSource>,
+    rom_hash: u64,
+    rom_size: usize,
+    // Instruction being executed, for watchpoint hits
+    opcode: u16,
+    watchpoints: Watchpoints,
+    tracer: Option<Tracer>,
+}
+
+// Registers VX..VY, in descending order when X > Y
+fn register_range(x: u8, y: u8) -> Vec<usize> {
+    let (x, y) = (x as usize, y as usize);
+    if x <= y { (x..=y).collect() } else { (y..=x).rev().collect() }
 }
 
 enum PCIncrement {
@@ -47,7 +81,18 @@ enum PCIncrement {
 
 impl Default for CPU {
     fn default() -> Self {
-        let mut ram = [0; 4096];
+        Self::new(Quirks::default())
+    }
+}
+
+impl CPU {
+    // Seeds CXNN from the OS; use `with_seed` for reproducible runs
+    pub fn new(quirks: Quirks) -> Self {
+        Self::with_seed(quirks, rand::random())
+    }
+
+    pub fn with_seed(quirks: Quirks, seed: u64) -> Self {
+        let mut ram = vec![0; quirks.memory_size()];
         let fontset: [u8; 80] = [
                 0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
                 0x20, 0x60, 0x20, 0x20, 0x70, // 1
@@ -66,321 +111,786 @@ impl Default for CPU {
                 0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
                 0xF0, 0x80, 0xF0, 0x80, 0x80  // F
                 ];
+        let big_fontset: [u8; 160] = [
+                0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+                0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+                0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+                0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+                0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+                0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+                0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+                0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+                0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+                0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+                0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+                0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+                0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+                0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+                0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+                0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0  // F
+                ];
 
         ram[FONT_OFFSET..(FONT_OFFSET+fontset.len())].copy_from_slice(&fontset);
+        ram[BIG_FONT_OFFSET..(BIG_FONT_OFFSET+big_fontset.len())].copy_from_slice(&big_fontset);
 
         Self {
             pc: START_RAM_ADDRESS as u16,
             registers: [0; 16],
-            timer: Timer { counter: 0, last_update: Instant::now() },
-            sound_timer: Timer { counter: 0, last_update: Instant::now() },
+            timer: Timer::default(),
+            sound_timer: Timer::default(),
             i: 0,
             ram,
-            vram: [[false; WIDTH as usize]; HEIGHT as usize],
+            vram: [[0; HIRES_WIDTH as usize]; HIRES_HEIGHT as usize],
             vram_changed: false,
+            hires: false,
+            plane: 1,
+            audio_pattern: None,
+            pitch: 64,
+            halted: false,
             stack: Vec::new(),
+            rpl: [0; 16],
             key_buffer: [false; 16],
+            key_wait: None,
+            quirks,
+            seed,
+            rng: Box::new(XorShiftRng::new(seed)),
+            rom_hash: snapshot::hash(&[]),
+            rom_size: 0,
+            opcode: 0,
+            watchpoints: Watchpoints::default(),
+            tracer: None,
         }
+    }
 
+    pub fn rom_hash(&self) -> u64 {
+        self.rom_hash
+    }
+
+    // Serializes the whole machine; the ROM itself is part of `ram`
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = Writer::new();
+        writer.u16(self.pc);
+        writer.u16(self.i);
+        writer.bytes(&self.registers);
+        // Unused stack slots are written too, keeping the layout fixed
+        writer.u8(self.stack.len() as u8);
+        for slot in 0..STACK_SIZE {
+            writer.u16(self.stack.get(slot).copied().unwrap_or(0));
+        }
+        writer.u8(self.timer.value());
+        writer.u8(self.sound_timer.value());
+        writer.u16(self.key_buffer.iter().enumerate().fold(0, |mask, (i, &pressed)| mask | ((pressed as u16) << i)));
+        writer.u8(self.key_wait.unwrap_or(0xFF));
+        writer.bool(self.hires);
+        writer.bool(self.halted);
+        writer.u8(self.plane);
+        writer.u8(self.pitch);
+        writer.bool(self.audio_pattern.is_some());
+        writer.bytes(&self.audio_pattern.unwrap_or_default());
+        writer.bytes(&self.rpl);
+        writer.u8(self.quirks.to_bits());
+        writer.u64(self.seed);
+        let rng = self.rng.state();
+        writer.u16(rng.len() as u16);
+        writer.bytes(&rng);
+        for row in self.vram.iter() {
+            writer.bytes(row);
+        }
+        writer.bytes(&self.ram);
+        writer.finish()
+    }
+
+    pub fn load_state(&mut self, state: &[u8]) -> Result<()> {
+        let mut reader = Reader::new(state);
+        let pc = reader.u16()?;
+        let i = reader.u16()?;
+        let registers = reader.array()?;
+        let depth = reader.u8()? as usize;
+        if depth > STACK_SIZE {
+            return Err(Error::InvalidSnapshot(format!("stack depth {}", depth)));
+        }
+        let mut stack = (0..STACK_SIZE).map(|_| reader.u16()).collect::<Result<Vec<_>>>()?;
+        stack.truncate(depth);
+        let delay = reader.u8()?;
+        let sound = reader.u8()?;
+        let keys = reader.u16()?;
+        let key_wait = reader.u8()?;
+        let hires = reader.bool()?;
+        let halted = reader.bool()?;
+        let plane = reader.u8()?;
+        let pitch = reader.u8()?;
+        let has_pattern = reader.bool()?;
+        let pattern = reader.array()?;
+        let rpl = reader.array()?;
+        let quirks = Quirks::from_bits(reader.u8()?);
+        let seed = reader.u64()?;
+        let rng_len = reader.u16()? as usize;
+        let rng = reader.bytes(rng_len)?;
+        let mut vram = [[0; HIRES_WIDTH as usize]; HIRES_HEIGHT as usize];
+        for row in vram.iter_mut() {
+            row.copy_from_slice(reader.bytes(HIRES_WIDTH as usize)?);
+        }
+        let ram = reader.bytes(quirks.memory_size())?;
+        if !reader.is_empty() {
+            return Err(Error::InvalidSnapshot("trailing data".to_string()));
+        }
+        if !self.rng.restore(rng) {
+            return Err(Error::InvalidSnapshot("random generator state does not match".to_string()));
+        }
+
+        self.pc = pc;
+        self.i = i;
+        self.registers = registers;
+        self.stack = stack;
+        self.timer.counter = delay;
+        self.sound_timer.counter = sound;
+        for (key, pressed) in self.key_buffer.iter_mut().enumerate() {
+            *pressed = keys & (1 << key) != 0;
+        }
+        self.key_wait = if key_wait == 0xFF { None } else { Some(key_wait & 0xF) };
+        self.hires = hires;
+        self.halted = halted;
+        self.plane = plane & 0b11;
+        self.pitch = pitch;
+        self.audio_pattern = if has_pattern { Some(pattern) } else { None };
+        self.rpl = rpl;
+        self.quirks = quirks;
+        self.seed = seed;
+        self.vram = vram;
+        self.vram_changed = true;
+        self.ram = ram.to_vec();
+        Ok(())
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    // Replaces the generator behind CXNN, e.g. with a fixed sequence for tests
+    pub fn set_rng(&mut self, rng: Box<dyn RandomSource>) {
+        self.rng = rng;
+    }
+
+    pub fn rng(&self) -> &dyn RandomSource {
+        self.rng.as_ref()
+    }
+
+    pub fn rng_mut(&mut self) -> &mut dyn RandomSource {
+        self.rng.as_mut()
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn registers(&self) -> &[u8; 16] {
+        &self.registers
+    }
+
+    pub fn index(&self) -> u16 {
+        self.i
+    }
+
+    pub fn stack(&self) -> &[u16] {
+        &self.stack
+    }
+
+    pub fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    // Addresses the ROM was loaded into
+    pub fn program_range(&self) -> std::ops::RangeInclusive<u16> {
+        START_RAM_ADDRESS as u16..=(START_RAM_ADDRESS + self.rom_size - 1) as u16
+    }
+
+    pub fn watchpoints(&self) -> &Watchpoints {
+        &self.watchpoints
+    }
+
+    pub fn watchpoints_mut(&mut self) -> &mut Watchpoints {
+        &mut self.watchpoints
+    }
+
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.tracer = tracer;
+    }
+
+    pub fn take_tracer(&mut self) -> Option<Tracer> {
+        self.tracer.take()
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+        self.ram.resize(quirks.memory_size(), 0);
+    }
+
+    pub fn width(&self) -> usize {
+        if self.hires { HIRES_WIDTH as usize } else { LORES_WIDTH as usize }
+    }
+
+    pub fn height(&self) -> usize {
+        if self.hires { HIRES_HEIGHT as usize } else { LORES_HEIGHT as usize }
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    pub fn vram(&self) -> &Framebuffer {
+        &self.vram
+    }
+
+    // Returns whether the framebuffer changed since the last call
+    pub fn take_vram_changed(&mut self) -> bool {
+        std::mem::take(&mut self.vram_changed)
+    }
+
+    // Hash of the visible screen, including its resolution
+    pub fn framebuffer_hash(&self) -> u64 {
+        let mut pixels = vec![self.width() as u8, self.height() as u8];
+        for row in self.vram.iter().take(self.height()) {
+            pixels.extend_from_slice(&row[..self.width()]);
+        }
+        snapshot::hash(&pixels)
+    }
+
+    pub fn keys(&self) -> &[bool; 16] {
+        &self.key_buffer
+    }
+
+    pub fn keys_mut(&mut self) -> &mut [bool; 16] {
+        &mut self.key_buffer
+    }
+
+    pub fn set_key(&mut self, key: u8, pressed: bool) {
+        self.key_buffer[key as usize & 0xF] = pressed;
+    }
+
+    pub fn tick_timers(&mut self) {
+        self.timer.tick();
+        self.sound_timer.tick();
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.timer.value()
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer.value()
+    }
+
+    pub fn sound_active(&self) -> bool {
+        !self.sound_timer.is_zero()
+    }
+
+    pub fn audio_pattern(&self) -> Option<[u8; 16]> {
+        self.audio_pattern
+    }
+
+    pub fn pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    // Only the bits of the selected planes are affected by clears, scrolls and sprites
+    fn blend(&self, current: u8, moved: u8) -> u8 {
+        (current & !self.plane) | (moved & self.plane)
     }
-}
 
-impl CPU {
     fn clear_vram(&mut self) -> PCIncrement {
-        self.vram = [[false; WIDTH as usize]; HEIGHT as usize];
+        for row in self.vram.iter_mut() {
+            for pixel in row.iter_mut() {
+                *pixel &= !self.plane;
+            }
+        }
+        self.vram_changed = true;
+        PCIncrement::Increment
+    }
+
+    fn set_hires(&mut self, hires: bool) -> PCIncrement {
+        self.hires = hires;
+        self.vram = [[0; HIRES_WIDTH as usize]; HIRES_HEIGHT as usize];
+        self.vram_changed = true;
+        PCIncrement::Increment
+    }
+
+    fn scroll_down(&mut self, n: usize) -> PCIncrement {
+        let (width, height) = (self.width(), self.height());
+        for y in (0..height).rev() {
+            for x in 0..width {
+                let moved = if y >= n { self.vram[y - n][x] } else { 0 };
+                self.vram[y][x] = self.blend(self.vram[y][x], moved);
+            }
+        }
         self.vram_changed = true;
         PCIncrement::Increment
     }
 
-    fn op_0x00EE(&mut self) -> PCIncrement {
-        if self.stack.len() < 2 {
-            panic!("Stack underflow");
+    fn scroll_up(&mut self, n: usize) -> PCIncrement {
+        let (width, height) = (self.width(), self.height());
+        for y in 0..height {
+            for x in 0..width {
+                let moved = if y + n < height { self.vram[y + n][x] } else { 0 };
+                self.vram[y][x] = self.blend(self.vram[y][x], moved);
+            }
         }
-        let pc = self.stack.pop().unwrap() as u16;
-        self.pc = (pc << 8) | self.stack.pop().unwrap() as u16; 
+        self.vram_changed = true;
         PCIncrement::Increment
     }
 
-    fn op_0xDXYN(&mut self, vx: u8, vy: u8, n: u8) -> PCIncrement {
-        let x = self.registers[vx as usize] % 64;
-        let y = self.registers[vy as usize] % 32;
+    fn scroll_right(&mut self, n: usize) -> PCIncrement {
+        let (width, height) = (self.width(), self.height());
+        for y in 0..height {
+            for x in (0..width).rev() {
+                let moved = if x >= n { self.vram[y][x - n] } else { 0 };
+                self.vram[y][x] = self.blend(self.vram[y][x], moved);
+            }
+        }
+        self.vram_changed = true;
+        PCIncrement::Increment
+    }
+
+    fn scroll_left(&mut self, n: usize) -> PCIncrement {
+        let (width, height) = (self.width(), self.height());
+        for y in 0..height {
+            for x in 0..width {
+                let moved = if x + n < width { self.vram[y][x + n] } else { 0 };
+                self.vram[y][x] = self.blend(self.vram[y][x], moved);
+            }
+        }
+        self.vram_changed = true;
+        PCIncrement::Increment
+    }
+
+    // Skips the next instruction, which is 4 bytes long when it is F000 NNNN
+    fn skip_next(&mut self) {
+        let next = self.pc.wrapping_add(2);
+        self.pc = if self.word_at(next) == 0xF000 { self.pc.wrapping_add(4) } else { next };
+    }
+
+    // Address I + offset, refusing to run off the end of RAM
+    fn index_address(&self, offset: usize) -> Result<usize> {
+        let address = self.i as usize + offset;
+        if address >= self.ram.len() {
+            return Err(Error::MemoryOutOfBounds { pc: self.pc, address });
+        }
+        Ok(address)
+    }
+
+    fn read_indexed(&mut self, offset: usize) -> Result<u8> {
+        let address = self.index_address(offset)?;
+        let value = self.ram[address];
+        self.watch(address, value, Access::Read);
+        Ok(value)
+    }
+
+    fn write_indexed(&mut self, offset: usize, value: u8) -> Result<()> {
+        let address = self.index_address(offset)?;
+        self.watch(address, value, Access::Write);
+        self.ram[address] = value;
+        Ok(())
+    }
+
+    fn watch(&mut self, address: usize, value: u8, access: Access) {
+        if !self.watchpoints.is_empty() {
+            let program = self.program_range();
+            self.watchpoints.check(self.pc, self.opcode, address as u16, value, access, program);
+        }
+    }
+
+    #[allow(non_snake_case)]
+    fn op_0x00EE(&mut self) -> Result<PCIncrement> {
+        match self.stack.pop() {
+            Some(pc) => self.pc = pc,
+            None => return Err(Error::StackUnderflow { pc: self.pc }),
+        }
+        Ok(PCIncrement::Increment)
+    }
+
+    #[allow(non_snake_case)]
+    fn op_0xDXYN(&mut self, vx: u8, vy: u8, n: u8) -> Result<PCIncrement> {
+        let x = self.registers[vx as usize] as usize % self.width();
+        let y = self.registers[vy as usize] as usize % self.height();
+        // DXY0 draws a 16x16 sprite stored as two bytes per row
+        let (rows, columns) = if n == 0 { (16, 16) } else { (n as usize, 8) };
+        let mut offset = 0;
         self.vram_changed = true;
         self.registers[0xF] = 0;
-        for i in 0..n {
-            let byte = self.ram[self.i as usize + i as usize];
-            let y = y + i;
-            if y >= 32 {
-                break;
+        // Each selected plane consumes its own copy of the sprite data
+        for mask in [0b01, 0b10] {
+            if self.plane & mask == 0 {
+                continue;
+            }
+            if self.draw_sprite(x, y, rows, columns, offset, mask)? {
+                self.registers[0xF] = 1;
             }
-            for j in 0..8 {
-                let x = x + j;
-                if x >= 64 {
+            offset += rows * columns / 8;
+        }
+        Ok(PCIncrement::Increment)
+    }
+
+    fn draw_sprite(&mut self, x: usize, y: usize, rows: usize, columns: usize, offset: usize, mask: u8) -> Result<bool> {
+        let (width, height) = (self.width(), self.height());
+        let mut collision = false;
+        for i in 0..rows {
+            let mut y = y + i;
+            if y >= height {
+                if !self.quirks.wrap_sprites {
                     break;
                 }
-                if byte & (0b10000000 >> j) != 0 {
-                    if self.vram[y as usize][x as usize] {
-                        self.registers[0xF] = 1;
-                        self.vram[y as usize][x as usize] = false;
-                    } else if !self.vram[y as usize][x as usize]  {
-                        self.vram[y as usize][x as usize] = true;
+                y %= height;
+            }
+            // Each byte is read once, however many of its pixels are drawn
+            let bytes = (0..columns / 8)
+                .map(|byte| self.read_indexed(offset + i * columns / 8 + byte))
+                .collect::<Result<Vec<u8>>>()?;
+            for j in 0..columns {
+                let mut x = x + j;
+                if x >= width {
+                    if !self.quirks.wrap_sprites {
+                        break;
                     }
+                    x %= width;
+                }
+                if bytes[j / 8] & (0b10000000 >> (j % 8)) != 0 {
+                    if self.vram[y][x] & mask != 0 {
+                        collision = true;
+                    }
+                    self.vram[y][x] ^= mask;
                 }
             }
         }
-        PCIncrement::Increment
+        Ok(collision)
     }
 
-    pub fn next_instruction(&mut self, opcode: u16) -> Result<(), String> {
-        println!("{} -: {:X}", self.pc, opcode);
-        let operation = (opcode & 0xF000) >> 12;
-        let next = match operation {
+    pub fn next_instruction(&mut self, opcode: u16) -> Result<()> {
+        self.opcode = opcode;
+        let next_word = self.word_at(self.pc.wrapping_add(2));
+        let next = match Instruction::decode(opcode, next_word) {
+            // Scroll down/up N lines
+            Instruction::ScrollDown(n) => self.scroll_down(n as usize),
+            Instruction::ScrollUp(n) => self.scroll_up(n as usize),
             // Clear VRAM
-            0x0 => {
-                let opcode = opcode & 0x00FF;
-                match opcode {
-                    0xE0 => {
-                        self.clear_vram()
-                    },
-                    // Return from subroutine
-                    0xEE => {
-                        self.op_0x00EE()
-                    },
-                    _ => {
-                        println!("Unknown opcode: {:X}", opcode);
-                        PCIncrement::Increment
-                    }
-                }
+            Instruction::Clear => self.clear_vram(),
+            // Return from subroutine
+            Instruction::Return => self.op_0x00EE()?,
+            // Scroll right/left 4 pixels
+            Instruction::ScrollRight => self.scroll_right(4),
+            Instruction::ScrollLeft => self.scroll_left(4),
+            // Exit interpreter
+            Instruction::Exit => {
+                self.halted = true;
+                PCIncrement::DontIncrement
             },
+            // Low/high resolution
+            Instruction::Lores => self.set_hires(false),
+            Instruction::Hires => self.set_hires(true),
             // Jump to address
-            0x1 => {
-                self.pc = opcode & 0x0FFF;
+            Instruction::Jump(address) => {
+                self.pc = address;
                 PCIncrement::DontIncrement
             },
             // Call subroutine
-            0x2 => {
-                let address = opcode & 0x0FFF;
-                self.stack.push((self.pc & 0xFF) as u8);
-                self.stack.push((self.pc >> 8) as u8);
+            Instruction::Call(address) => {
+                if self.stack.len() >= STACK_SIZE {
+                    return Err(Error::StackOverflow { pc: self.pc });
+                }
+                self.stack.push(self.pc);
                 self.pc = address;
                 PCIncrement::DontIncrement
             },
             // Skip if equal
-            0x3 => {
-                let register = ((opcode & 0x0F00) >> 8) as u8;
-                let value = (opcode & 0x00FF) as u8;
-                if value == self.registers[register as usize] {
-                    self.pc += 2;
+            Instruction::SkipIfEqual(x, value) => {
+                if value == self.registers[x as usize] {
+                    self.skip_next();
                 }
                 PCIncrement::Increment
             },
             // Skip if not equal
-            0x4 => {
-                let register = ((opcode & 0x0F00) >> 8) as u8;
-                let value = (opcode & 0x00FF) as u8;
-                if value != self.registers[register as usize] {
-                    self.pc += 2;
+            Instruction::SkipIfNotEqual(x, value) => {
+                if value != self.registers[x as usize] {
+                    self.skip_next();
                 }
                 PCIncrement::Increment
             },
             // Skip if register equal
-            0x5 => {
-                let vx = ((opcode & 0x0F00) >> 8) as u8;
-                let vy = ((opcode & 0x00F0) >> 4) as u8;
-                if self.registers[vx as usize] == self.registers[vy as usize] {
-                    self.pc += 2;
+            Instruction::SkipIfRegistersEqual(x, y) => {
+                if self.registers[x as usize] == self.registers[y as usize] {
+                    self.skip_next();
+                }
+                PCIncrement::Increment
+            },
+            Instruction::SkipIfRegistersNotEqual(x, y) => {
+                if self.registers[x as usize] != self.registers[y as usize] {
+                    self.skip_next();
+                }
+                PCIncrement::Increment
+            },
+            // Save registers VX..VY
+            Instruction::SaveRange(x, y) => {
+                for (offset, register) in register_range(x, y).into_iter().enumerate() {
+                    self.write_indexed(offset, self.registers[register])?;
+                }
+                PCIncrement::Increment
+            },
+            // Load registers VX..VY
+            Instruction::LoadRange(x, y) => {
+                for (offset, register) in register_range(x, y).into_iter().enumerate() {
+                    self.registers[register] = self.read_indexed(offset)?;
                 }
                 PCIncrement::Increment
             },
             // Set register to value
-            0x6 => {
-                let x = ((opcode & 0x0F00) >> 8) as u8;
-                let nn = (opcode & 0x00FF) as u8;
-                self.registers[x as usize] = nn;
+            Instruction::Set(x, value) => {
+                self.registers[x as usize] = value;
                 PCIncrement::Increment
             },
             // Add value to register
-            0x7 => {
-                let x  = ((opcode & 0x0F00) >> 8) as u8;
-                let nn = (opcode & 0x00FF) as u8;
-                self.registers[x as usize] = self.registers[x as usize].wrapping_add(nn);
+            Instruction::Add(x, value) => {
+                self.registers[x as usize] = self.registers[x as usize].wrapping_add(value);
                 PCIncrement::Increment
             },
             // ALU
-            0x8 => {
-                let operation = opcode & 0x000F;
-                let vx = ((opcode & 0x0F00) >> 8) as u8;
-                let vy = ((opcode & 0x00F0) >> 4) as u8;
-                match operation {
-                    0x0 => self.registers[vx as usize] = self.registers[vy as usize],
-                    0x1 => self.registers[vx as usize] |= self.registers[vy as usize],
-                    0x2 => self.registers[vx as usize] &= self.registers[vy as usize],
-                    0x3 => self.registers[vx as usize] ^= self.registers[vy as usize],
-                    0x4 => {
-                        let (result, overflow) = self.registers[vx as usize].overflowing_add(self.registers[vy as usize]);
-                        self.registers[vx as usize] = result;
-                        self.registers[0xF] =  overflow as u8;
-                    },
-                    0x5 => {
-                        let (result, overflow) = self.registers[vx as usize].overflowing_sub(self.registers[vy as usize]);
-                        self.registers[vx as usize] = result;
-                        self.registers[0xF] =  !overflow as u8;
-                    },
-                    0x6 => {
-                        self.registers[0xF] = self.registers[vx as usize] & 0x1;
-                        self.registers[vx as usize] >>= 1;
+            Instruction::Copy(x, y) => {
+                self.registers[x as usize] = self.registers[y as usize];
+                PCIncrement::Increment
+            },
+            Instruction::Or(x, y) => self.logic(x, y, |vx, vy| vx | vy),
+            Instruction::And(x, y) => self.logic(x, y, |vx, vy| vx & vy),
+            Instruction::Xor(x, y) => self.logic(x, y, |vx, vy| vx ^ vy),
+            Instruction::AddRegister(x, y) => {
+                let (result, overflow) = self.registers[x as usize].overflowing_add(self.registers[y as usize]);
+                self.registers[x as usize] = result;
+                self.registers[0xF] = overflow as u8;
+                PCIncrement::Increment
+            },
+            Instruction::Subtract(x, y) => {
+                let (result, overflow) = self.registers[x as usize].overflowing_sub(self.registers[y as usize]);
+                self.registers[x as usize] = result;
+                self.registers[0xF] = !overflow as u8;
+                PCIncrement::Increment
+            },
+            Instruction::ShiftRight(x, y) => {
+                let value = if self.quirks.shift_uses_vy { self.registers[y as usize] } else { self.registers[x as usize] };
+                self.registers[x as usize] = value >> 1;
+                self.registers[0xF] = value & 0x1;
+                PCIncrement::Increment
+            },
+            Instruction::SubtractReversed(x, y) => {
+                let (result, overflow) = self.registers[y as usize].overflowing_sub(self.registers[x as usize]);
+                self.registers[x as usize] = result;
+                self.registers[0xF] = !overflow as u8;
+                PCIncrement::Increment
+            },
+            Instruction::ShiftLeft(x, y) => {
+                let value = if self.quirks.shift_uses_vy { self.registers[y as usize] } else { self.registers[x as usize] };
+                self.registers[x as usize] = value << 1;
+                self.registers[0xF] = (value & 0x80) >> 7;
+                PCIncrement::Increment
+            },
+            Instruction::SetIndex(address) => {
+                self.i = address;
+                PCIncrement::Increment
+            },
+            Instruction::JumpOffset(address) => {
+                let register = if self.quirks.jump_uses_vx { (address >> 8) as usize } else { 0 };
+                self.pc = address + self.registers[register] as u16;
+                PCIncrement::DontIncrement
+            },
+            Instruction::Random(x, mask) => {
+                let random = self.rng.next_byte();
+                self.registers[x as usize] = random & mask;
+                PCIncrement::Increment
+            },
+            Instruction::Draw(x, y, n) => self.op_0xDXYN(x, y, n)?,
+            // Keyboard, only the low nibble selects a key
+            Instruction::SkipIfKey(x) => {
+                if self.key_buffer[(self.registers[x as usize] & 0xF) as usize] {
+                    self.skip_next();
+                }
+                PCIncrement::Increment
+            },
+            Instruction::SkipIfNotKey(x) => {
+                if !self.key_buffer[(self.registers[x as usize] & 0xF) as usize] {
+                    self.skip_next();
+                }
+                PCIncrement::Increment
+            },
+            // Load I with the 16-bit address following the opcode
+            Instruction::SetIndexLong(address) => {
+                self.i = address;
+                self.pc = self.pc.wrapping_add(2);
+                PCIncrement::Increment
+            },
+            // Select drawing planes
+            Instruction::Plane(planes) => {
+                self.plane = planes & 0b11;
+                PCIncrement::Increment
+            },
+            // Load audio pattern buffer
+            Instruction::Audio => {
+                let mut pattern = [0; 16];
+                for (offset, byte) in pattern.iter_mut().enumerate() {
+                    *byte = self.read_indexed(offset)?;
+                }
+                self.audio_pattern = Some(pattern);
+                PCIncrement::Increment
+            },
+            Instruction::GetDelay(x) => {
+                self.registers[x as usize] = self.timer.counter;
+                PCIncrement::Increment
+            },
+            // Wait for a key to be pressed and then released
+            Instruction::WaitKey(x) => {
+                match self.key_wait {
+                    None => {
+                        self.key_wait = self.key_buffer.iter().position(|&pressed| pressed).map(|key| key as u8);
+                        self.pc = self.pc.wrapping_sub(2);
                     },
-                    0x7 => {
-                        let (result, overflow) = self.registers[vy as usize].overflowing_sub(self.registers[vx as usize]);
-                        self.registers[vx as usize] = result;
-                        self.registers[0xF] =  !overflow as u8;
+                    Some(key) if self.key_buffer[key as usize] => {
+                        self.pc = self.pc.wrapping_sub(2);
                     },
-                    0xE => {
-                        self.registers[0xF] = (self.registers[vx as usize] & 0x80) >> 7;
-                        self.registers[vx as usize] <<= 1;
+                    Some(key) => {
+                        self.registers[x as usize] = key;
+                        self.key_wait = None;
                     },
-                    _ => return Err(format!("Unknown operation: {:X}", operation))
                 }
                 PCIncrement::Increment
             },
-            0x9 => {
-                let vx = ((opcode & 0x0F00) >> 8) as u8;
-                let vy = ((opcode & 0x00F0) >> 4) as u8;
-                if self.registers[vx as usize] != self.registers[vy as usize] {
-                    self.pc += 2;
+            Instruction::SetDelay(x) => {
+                self.timer.counter = self.registers[x as usize];
+                PCIncrement::Increment
+            },
+            Instruction::SetSound(x) => {
+                self.sound_timer.counter = self.registers[x as usize];
+                PCIncrement::Increment
+            },
+            Instruction::AddIndex(x) => {
+                self.i = self.i.wrapping_add(self.registers[x as usize] as u16);
+                if self.quirks.index_overflow_sets_vf {
+                    self.registers[0xF] = (self.i > 0xFFF) as u8;
                 }
                 PCIncrement::Increment
-            }
-            0xA => {
-                let value = opcode & 0x0FFF;
-                self.i = value;
+            },
+            Instruction::Font(x) => {
+                self.i = FONT_OFFSET as u16 + (self.registers[x as usize] & 0xF) as u16 * 5;
                 PCIncrement::Increment
             },
-            0xB => {
-                let value = opcode & 0x0FFF;
-                self.pc = value + self.registers[0] as u16;
-                PCIncrement::DontIncrement
+            Instruction::BigFont(x) => {
+                self.i = BIG_FONT_OFFSET as u16 + (self.registers[x as usize] & 0xF) as u16 * 10;
+                PCIncrement::Increment
             },
-            0xC => {
-                let x = ((opcode & 0x0F00) >> 8) as u8;
-                let nn = (opcode & 0x00FF) as u8;
-                let random = rand::random::<u8>();
-                self.registers[x as usize] = random & nn;
+            Instruction::Bcd(x) => {
+                let value = self.registers[x as usize];
+                self.write_indexed(0, value / 100)?;
+                self.write_indexed(1, (value / 10) % 10)?;
+                self.write_indexed(2, (value % 100) % 10)?;
                 PCIncrement::Increment
-            }
-            0xD => {
-                let n = (opcode & 0x000F) as u8;
-                let vy = ((opcode & 0x0F0) >> 4) as u8;
-                let vx = ((opcode & 0xF00) >> 8) as u8;
-                self.op_0xDXYN(vx, vy, n)
-            },
-            // Keyboard
-            0xE => {
-                let opcode = opcode & 0x00FF;
-                let x = ((opcode & 0x0F00) >> 8) as usize;
-                let key = self.registers[x];
-                if key > 0xF {
-                    println!("Invalid keypad key: {:X}", key);
-                } 
-                let key = key % 0xF;
-                match opcode {
-                    0x9E => {
-                        if self.key_buffer[key as usize] {
-                            self.pc += 2;
-                        }
-                    },
-                    0xA1 => {
-                        if !self.key_buffer[key as usize] {
-                            self.pc += 2;
-                        }
-                    },
-                    _ => return Err(format!("Unknown operation: {:X}", opcode))
+            },
+            Instruction::Pitch(x) => {
+                self.pitch = self.registers[x as usize];
+                PCIncrement::Increment
+            },
+            Instruction::Store(x) => {
+                for i in 0..=x {
+                    self.write_indexed(i as usize, self.registers[i as usize])?;
+                }
+                if self.quirks.load_store_increments_i {
+                    self.i = self.i.wrapping_add(x as u16 + 1);
                 }
                 PCIncrement::Increment
-            }
-            0xF => {
-                let operation = opcode & 0x00FF;
-                let x = ((opcode & 0x0F00) >> 8) as u8;
-                match operation {
-                    0x07 => self.registers[x as usize] = self.timer.counter,
-                    0x0A => {
-                        let mut key_pressed = false;
-                        for (i, key) in self.key_buffer.iter().enumerate() {
-                            if *key {
-                                self.registers[x as usize] = i as u8;
-                                key_pressed = true;
-                                break;
-                            }
-                        }
-                        if !key_pressed {
-                            self.pc -= 2;
-                        } 
-                    },
-                    0x15 => self.timer.counter = self.registers[x as usize],
-                    0x18 => self.sound_timer.counter = self.registers[x as usize],
-                    0x1E => {
-                        self.i += self.registers[x as usize] as u16;
-                    },
-                    0x29 => {
-                        self.i = FONT_OFFSET as u16 + self.registers[x as usize] as u16
-                    },
-                    0x33 => {
-                        let value = self.registers[x as usize];
-                        self.ram[self.i as usize] = value / 100;
-                        self.ram[self.i as usize + 1] = (value / 10) % 10;
-                        self.ram[self.i as usize + 2] = (value % 100) % 10;
-                    },
-                    0x55 => {                        
-                        for i in 0..=x {
-                            self.ram[self.i as usize + i as usize] = self.registers[i as usize];
-                        }
-                    },
-                    0x65 => {
-                        for i in 0..=x {
-                            self.registers[i as usize] = self.ram[self.i as usize + i as usize];
-                        }
-                    }
-                    _ => {
-                        eprintln!("Unknown opcode: {:X}", opcode);
-                    }
+            },
+            Instruction::Load(x) => {
+                for i in 0..=x {
+                    self.registers[i as usize] = self.read_indexed(i as usize)?;
+                }
+                if self.quirks.load_store_increments_i {
+                    self.i = self.i.wrapping_add(x as u16 + 1);
                 }
                 PCIncrement::Increment
             },
-            _ => { 
-                eprintln!("Unknown opcode: {:X}", opcode);
+            // Save/restore RPL user flags
+            Instruction::SaveFlags(x) => {
+                self.rpl[..=x as usize].copy_from_slice(&self.registers[..=x as usize]);
                 PCIncrement::Increment
             },
+            Instruction::LoadFlags(x) => {
+                self.registers[..=x as usize].copy_from_slice(&self.rpl[..=x as usize]);
+                PCIncrement::Increment
+            },
+            Instruction::Unknown(opcode) => return Err(Error::UnknownOpcode { pc: self.pc, opcode }),
         };
-        if self.pc >= 0xFFF {
-            self.pc %= 0xFFF;
-        }
         match next {
             PCIncrement::DontIncrement => (),
-            PCIncrement::Increment => self.pc += 2,
+            PCIncrement::Increment => self.pc = self.pc.wrapping_add(2),
         }
         Ok(())
     }
 
+    // 8XY1/8XY2/8XY3, which reset VF under the logic_resets_vf quirk
+    fn logic(&mut self, x: u8, y: u8, operation: impl Fn(u8, u8) -> u8) -> PCIncrement {
+        self.registers[x as usize] = operation(self.registers[x as usize], self.registers[y as usize]);
+        if self.quirks.logic_resets_vf {
+            self.registers[0xF] = 0;
+        }
+        PCIncrement::Increment
+    }
+
+    // Wraps around the end of memory like the PC does
+    fn word_at(&self, address: u16) -> u16 {
+        let at = |address: u16| self.ram[address as usize % self.ram.len()] as u16;
+        at(address) << 8 | at(address.wrapping_add(1))
+    }
+
     pub fn fetch_opcode(&self) -> u16 {
-        (self.ram[self.pc as usize] as u16) << 8 | self.ram[self.pc as usize + 1] as u16
+        self.word_at(self.pc)
     }
 
-    pub fn cycle(&mut self, driver: &mut DisplayDriver) -> Result<(), String> {
-        let opcode = self.fetch_opcode();
-        self.next_instruction(opcode)?;
-        if self.vram_changed {
-            driver.draw(&self.vram)?;
-            self.vram_changed = false;
+    pub fn cycle(&mut self) -> Result<()> {
+        if self.halted {
+            return Ok(());
         }
-        Ok(())
+        let opcode = self.fetch_opcode();
+        let Some(mut tracer) = self.tracer.take() else {
+            return self.next_instruction(opcode);
+        };
+        // Disassembled first, as the instruction may overwrite itself
+        let (pc, text) = (self.pc, disasm::disassemble(&self.ram, self.pc as usize).0);
+        let before = self.trace_state();
+        let result = self.next_instruction(opcode);
+        let written = tracer.record(pc, opcode, &text, before, self.trace_state());
+        self.tracer = Some(tracer);
+        result?;
+        Ok(written?)
+    }
+
+    fn trace_state(&self) -> trace::State {
+        trace::State { registers: self.registers, index: self.i }
     }
 
-    pub fn load_ram(&mut self, path: &str) -> Result<(), String> {
+    pub fn load_ram(&mut self, path: &str) -> Result<()> {
         let mut file = match std::fs::File::open(path) {
             Ok(file) => file,
-            Err(_) => return Err(format!("Could not open file {}", path)),
+            Err(_) => return Err(Error::RomNotFound { path: path.to_string() }),
         };
-        
-        file.read_exact(&mut self.ram[START_RAM_ADDRESS..]).unwrap();
+
+        let mut rom = Vec::new();
+        file.read_to_end(&mut rom)?;
+        self.load_rom(&rom)
+    }
+
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<()> {
+        let max = self.ram.len() - START_RAM_ADDRESS;
+        if rom.len() > max {
+            return Err(Error::RomTooLarge { size: rom.len(), max });
+        }
+        self.ram[START_RAM_ADDRESS..START_RAM_ADDRESS + rom.len()].copy_from_slice(rom);
+        self.rom_hash = snapshot::hash(rom);
+        self.rom_size = rom.len();
         Ok(())
     }
 }
@@ -0,0 +1,546 @@
+use std::fmt::Write as _;
+use std::io::{BufRead, Write};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+
+use crate::cpu::CPU;
+use crate::disasm::{self, Labels};
+use crate::error::Error;
+use crate::watch::{Hit, WatchAction, Watchpoint};
+
+pub const HELP: &str = "\
+Commands (an empty line repeats the last one):
+  c, continue            Resume execution
+  p, pause               Break at the next instruction
+  s, step [N]            Execute N instructions, entering calls [default: 1]
+  n, next                Execute one instruction, running calls to completion
+  u, until <ADDR>        Run until PC reaches ADDR
+  b, break [ADDR [if <COND>]]
+                         Break at ADDR, optionally only when COND holds,
+                         e.g. b 0x23C if V3 == 0x10; list breakpoints without ADDR
+  d, delete [N]          Delete breakpoint N, or all of them
+  w, watch [RANGE]       Break on memory accessed through I in RANGE, written
+                         START[-END][:r|w|rw] or program[:r|w|rw] for the
+                         ROM's bytes; list watchpoints without RANGE
+  uw, unwatch [N]        Delete watchpoint N, or all of them
+  r, regs                Show registers, I, stack, timers and keys
+  x, dump [ADDR [LEN]]   Hexdump LEN bytes of memory [default: I, 64]
+  l, list [ADDR]         Disassemble around ADDR [default: PC]
+  q, quit                Exit the emulator
+  h, help                Print this help
+Values are decimal, 0x/# prefixed hex or one of V0-VF, I, PC, DT, ST, SP.
+Addresses can also be labels, when running Octo source.
+Conditions compare two values with ==, !=, <, <=, > or >=.
+";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operand {
+    Register(usize),
+    Index,
+    Pc,
+    Delay,
+    Sound,
+    StackDepth,
+    Value(u16),
+}
+
+impl Operand {
+    fn parse(text: &str) -> Option<Self> {
+        let upper = text.to_ascii_uppercase();
+        let operand = match upper.as_str() {
+            "I" => Operand::Index,
+            "PC" => Operand::Pc,
+            "DT" => Operand::Delay,
+            "ST" => Operand::Sound,
+            "SP" => Operand::StackDepth,
+            _ => match upper.strip_prefix('V') {
+                Some(register) if register.len() == 1 => Operand::Register(usize::from_str_radix(register, 16).ok()?),
+                _ => Operand::Value(parse_number(&upper)?),
+            },
+        };
+        Some(operand)
+    }
+
+    fn value(self, cpu: &CPU) -> u16 {
+        match self {
+            Operand::Register(register) => cpu.registers()[register] as u16,
+            Operand::Index => cpu.index(),
+            Operand::Pc => cpu.pc(),
+            Operand::Delay => cpu.delay_timer() as u16,
+            Operand::Sound => cpu.sound_timer() as u16,
+            Operand::StackDepth => cpu.stack().len() as u16,
+            Operand::Value(value) => value,
+        }
+    }
+}
+
+fn parse_number(text: &str) -> Option<u16> {
+    let lower = text.to_ascii_lowercase();
+    match lower.strip_prefix("0x").or_else(|| lower.strip_prefix('#')) {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => lower.parse().ok(),
+    }
+}
+
+const COMPARISONS: [&str; 6] = ["==", "!=", "<=", ">=", "<", ">"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Condition {
+    left: Operand,
+    comparison: &'static str,
+    right: Operand,
+    text: String,
+}
+
+impl Condition {
+    pub fn parse(text: &str) -> Option<Self> {
+        // Two character operators come first so "<=" isn't read as "<"
+        let (position, comparison) = COMPARISONS
+            .iter()
+            .find_map(|&comparison| text.find(comparison).map(|position| (position, comparison)))?;
+        Some(Self {
+            left: Operand::parse(text[..position].trim())?,
+            comparison,
+            right: Operand::parse(text[position + comparison.len()..].trim())?,
+            text: text.trim().to_string(),
+        })
+    }
+
+    pub fn holds(&self, cpu: &CPU) -> bool {
+        let (left, right) = (self.left.value(cpu), self.right.value(cpu));
+        match self.comparison {
+            "==" => left == right,
+            "!=" => left != right,
+            "<=" => left <= right,
+            ">=" => left >= right,
+            "<" => left < right,
+            _ => left > right,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub address: u16,
+    pub condition: Option<Condition>,
+}
+
+// One-shot stop used by step over and run-to-address. Step over also waits
+// for the stack to unwind back to where the call was made.
+struct Target {
+    address: u16,
+    depth: Option<usize>,
+}
+
+pub enum Action {
+    Continue,
+    Quit,
+}
+
+// Breakpoint and stepping state, driven by text commands. The emulator asks
+// `should_break` before every instruction and skips frames while paused, so
+// the frontend keeps drawing and polling input in the meantime.
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    target: Option<Target>,
+    paused: bool,
+    // Set when resuming so the breakpoint at PC doesn't trigger right away
+    resuming: bool,
+    lines: Option<Receiver<String>>,
+    last_line: String,
+    labels: Labels,
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debugger {
+    // Starts paused, before the first instruction
+    pub fn new() -> Self {
+        Self {
+            breakpoints: Vec::new(),
+            target: None,
+            paused: true,
+            resuming: false,
+            lines: None,
+            last_line: String::new(),
+            labels: Labels::new(),
+        }
+    }
+
+    // Reads commands from stdin on a background thread so the frontend isn't
+    // blocked while waiting for input
+    pub fn with_terminal() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            for line in std::io::stdin().lock().lines() {
+                let Ok(line) = line else { break };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        println!("Debugger attached, type help for the list of commands");
+        let debugger = Self {
+            lines: Some(receiver),
+            ..Self::new()
+        };
+        debugger.prompt();
+        debugger
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    // Names shown for addresses and accepted in place of them
+    pub fn set_labels(&mut self, labels: Labels) {
+        self.labels = labels;
+    }
+
+    pub fn add_breakpoint(&mut self, address: u16, condition: Option<Condition>) {
+        self.breakpoints.push(Breakpoint { address, condition });
+    }
+
+    // Runs the commands typed since the last frame. Closing the terminal
+    // input while paused quits, as nothing could resume the program.
+    pub fn update(&mut self, cpu: &mut CPU) -> Action {
+        loop {
+            let Some(lines) = &self.lines else { return Action::Continue };
+            let line = match lines.try_recv() {
+                Ok(line) => line,
+                Err(TryRecvError::Empty) => return Action::Continue,
+                Err(TryRecvError::Disconnected) if self.paused => return Action::Quit,
+                Err(TryRecvError::Disconnected) => return Action::Continue,
+            };
+            let (output, action) = self.execute(&line, cpu);
+            print!("{}", output);
+            if let Action::Quit = action {
+                return action;
+            }
+            self.prompt();
+        }
+    }
+
+    fn prompt(&self) {
+        if self.paused {
+            print!("(c8db) ");
+            let _ = std::io::stdout().flush();
+        }
+    }
+
+    // Checked before each instruction while running
+    pub fn should_break(&mut self, cpu: &CPU) -> bool {
+        if std::mem::take(&mut self.resuming) {
+            return false;
+        }
+        let pc = cpu.pc();
+        let reached = self
+            .target
+            .as_ref()
+            .is_some_and(|target| target.address == pc && target.depth.is_none_or(|depth| cpu.stack().len() <= depth));
+        let hit = self.breakpoint_hit(cpu);
+        if !reached && hit.is_none() {
+            return false;
+        }
+        self.target = None;
+        self.paused = true;
+        match hit {
+            Some(index) => println!("Breakpoint {} at {}", index + 1, self.location(cpu, pc)),
+            None => println!("Stopped at {}", self.location(cpu, pc)),
+        }
+        self.prompt();
+        true
+    }
+
+    fn breakpoint_hit(&self, cpu: &CPU) -> Option<usize> {
+        self.breakpoints.iter().position(|breakpoint| {
+            breakpoint.address == cpu.pc() && breakpoint.condition.as_ref().is_none_or(|condition| condition.holds(cpu))
+        })
+    }
+
+    // Pauses on watchpoints set to break
+    pub fn report_watch_hits(&mut self, cpu: &CPU, hits: &[Hit]) {
+        self.target = None;
+        self.paused = true;
+        for hit in hits {
+            println!("{}", hit);
+        }
+        println!("  now at {}", self.location(cpu, cpu.pc()));
+        self.prompt();
+    }
+
+    // Pauses on an emulation error instead of exiting
+    pub fn report_error(&mut self, cpu: &CPU, error: &Error) {
+        self.target = None;
+        self.paused = true;
+        println!("Stopped by error: {}", error);
+        println!("  at {}", self.location(cpu, cpu.pc()));
+        self.prompt();
+    }
+
+    // Runs one command line and returns its output
+    pub fn execute(&mut self, line: &str, cpu: &mut CPU) -> (String, Action) {
+        let line = if line.trim().is_empty() {
+            self.last_line.clone()
+        } else {
+            self.last_line = line.trim().to_string();
+            line.trim().to_string()
+        };
+        let mut words = line.split_whitespace();
+        let Some(command) = words.next() else { return (String::new(), Action::Continue) };
+        let arguments: Vec<&str> = words.collect();
+        let mut out = String::new();
+        match (command, arguments.as_slice()) {
+            ("c" | "continue", []) => self.resume(),
+            ("p" | "pause", []) => {
+                self.paused = true;
+                writeln!(out, "Paused at {}", self.location(cpu, cpu.pc())).unwrap();
+            },
+            ("s" | "step", arguments) if arguments.len() <= 1 => {
+                let count = match arguments.first().map(|text| parse_number(text)) {
+                    None => 1,
+                    Some(Some(count)) => count,
+                    Some(None) => return (format!("Invalid step count {}\n", arguments[0]), Action::Continue),
+                };
+                self.step(cpu, count, &mut out);
+            },
+            ("n" | "next", []) => {
+                let pc = cpu.pc();
+                if cpu.fetch_opcode() & 0xF000 == 0x2000 {
+                    self.target = Some(Target { address: pc.wrapping_add(2), depth: Some(cpu.stack().len()) });
+                    self.resume();
+                } else {
+                    self.step(cpu, 1, &mut out);
+                }
+            },
+            ("u" | "until", [address]) => match self.address(address, cpu) {
+                Some(address) => {
+                    self.target = Some(Target { address, depth: None });
+                    self.resume();
+                },
+                None => writeln!(out, "Invalid address {}", address).unwrap(),
+            },
+            ("b" | "break", []) => {
+                if self.breakpoints.is_empty() {
+                    writeln!(out, "No breakpoints").unwrap();
+                }
+                for (index, breakpoint) in self.breakpoints.iter().enumerate() {
+                    write!(out, "{}: 0x{:04X}{}", index + 1, breakpoint.address, self.symbol(breakpoint.address)).unwrap();
+                    if let Some(condition) = &breakpoint.condition {
+                        write!(out, " if {}", condition.text).unwrap();
+                    }
+                    writeln!(out).unwrap();
+                }
+            },
+            ("b" | "break", [address, rest @ ..]) => {
+                let Some(address) = self.address(address, cpu) else {
+                    return (format!("Invalid address {}\n", address), Action::Continue);
+                };
+                let condition = match rest {
+                    [] => None,
+                    ["if", condition @ ..] => match Condition::parse(&condition.join(" ")) {
+                        Some(condition) => Some(condition),
+                        None => return (format!("Invalid condition {}\n", condition.join(" ")), Action::Continue),
+                    },
+                    _ => return ("Expected: break <ADDR> [if <COND>]\n".to_string(), Action::Continue),
+                };
+                self.add_breakpoint(address, condition);
+                writeln!(out, "Breakpoint {} at 0x{:04X}{}", self.breakpoints.len(), address, self.symbol(address)).unwrap();
+            },
+            ("d" | "delete", []) => {
+                self.breakpoints.clear();
+                writeln!(out, "Deleted all breakpoints").unwrap();
+            },
+            ("d" | "delete", [number]) => match number.parse::<usize>() {
+                Ok(number) if (1..=self.breakpoints.len()).contains(&number) => {
+                    self.breakpoints.remove(number - 1);
+                    writeln!(out, "Deleted breakpoint {}", number).unwrap();
+                },
+                _ => writeln!(out, "No breakpoint {}", number).unwrap(),
+            },
+            ("w" | "watch", []) => {
+                if cpu.watchpoints().is_empty() {
+                    writeln!(out, "No watchpoints").unwrap();
+                }
+                for (index, watchpoint) in cpu.watchpoints().list().iter().enumerate() {
+                    let action = match watchpoint.action {
+                        WatchAction::Break => "break",
+                        WatchAction::Log => "log",
+                    };
+                    writeln!(out, "{}: {} ({})", index + 1, watchpoint, action).unwrap();
+                }
+            },
+            ("w" | "watch", [range]) => match Watchpoint::parse(range, WatchAction::Break) {
+                Some(watchpoint) => {
+                    cpu.watchpoints_mut().add(watchpoint);
+                    writeln!(out, "Watchpoint {} on {}", cpu.watchpoints().list().len(), watchpoint).unwrap();
+                },
+                None => writeln!(out, "Invalid range {}", range).unwrap(),
+            },
+            ("uw" | "unwatch", []) => {
+                cpu.watchpoints_mut().clear();
+                writeln!(out, "Deleted all watchpoints").unwrap();
+            },
+            ("uw" | "unwatch", [number]) => match number.parse::<usize>().ok().and_then(|number| cpu.watchpoints_mut().remove(number.wrapping_sub(1))) {
+                Some(_) => writeln!(out, "Deleted watchpoint {}", number).unwrap(),
+                None => writeln!(out, "No watchpoint {}", number).unwrap(),
+            },
+            ("r" | "regs", []) => registers(cpu, &mut out),
+            ("x" | "dump", arguments) if arguments.len() <= 2 => {
+                let operands: Option<Vec<u16>> = arguments
+                    .iter()
+                    .map(|text| self.address(text, cpu))
+                    .collect();
+                match operands.as_deref() {
+                    Some([]) => hexdump(cpu, cpu.index(), 64, &mut out),
+                    Some([address]) => hexdump(cpu, *address, 64, &mut out),
+                    Some([address, length]) => hexdump(cpu, *address, *length, &mut out),
+                    _ => writeln!(out, "Invalid address or length").unwrap(),
+                }
+            },
+            ("l" | "list", []) => self.list(cpu, cpu.pc().saturating_sub(8), &mut out),
+            ("l" | "list", [address]) => match self.address(address, cpu) {
+                Some(address) => self.list(cpu, address, &mut out),
+                None => writeln!(out, "Invalid address {}", address).unwrap(),
+            },
+            ("q" | "quit", []) => return (out, Action::Quit),
+            ("h" | "help", []) => out.push_str(HELP),
+            _ => writeln!(out, "Unknown command {}, type help for the list of commands", line).unwrap(),
+        }
+        (out, Action::Continue)
+    }
+
+    // A label name or an operand
+    fn address(&self, text: &str, cpu: &CPU) -> Option<u16> {
+        match self.labels.iter().find(|(_, label)| *label == text) {
+            Some((&address, _)) => Some(address),
+            None => Operand::parse(text).map(|operand| operand.value(cpu)),
+        }
+    }
+
+    // " <label+offset>" for the closest label at or before `address`
+    fn symbol(&self, address: u16) -> String {
+        match self.labels.range(..=address).next_back() {
+            Some((&start, label)) if start == address => format!(" <{}>", label),
+            Some((&start, label)) => format!(" <{}+{}>", label, address - start),
+            None => String::new(),
+        }
+    }
+
+    fn location(&self, cpu: &CPU, pc: u16) -> String {
+        let text = disasm::disassemble_labelled(cpu.ram(), pc as usize, &self.labels).0;
+        format!("0x{:04X}{}: {}", pc, self.symbol(pc), text)
+    }
+
+    fn resume(&mut self) {
+        self.paused = false;
+        self.resuming = true;
+    }
+
+    fn step(&mut self, cpu: &mut CPU, count: u16, out: &mut String) {
+        self.paused = true;
+        for _ in 0..count.max(1) {
+            if cpu.is_halted() {
+                writeln!(out, "Program exited").unwrap();
+                return;
+            }
+            if let Err(error) = cpu.cycle() {
+                writeln!(out, "Stopped by error: {}", error).unwrap();
+                break;
+            }
+            let hits = cpu.watchpoints_mut().take_hits();
+            for hit in &hits {
+                writeln!(out, "{}", hit).unwrap();
+            }
+            if hits.iter().any(|hit| hit.action == WatchAction::Break) {
+                break;
+            }
+            // Longer runs stop at breakpoints like continue does
+            if count > 1 && self.breakpoint_hit(cpu).is_some() {
+                break;
+            }
+        }
+        writeln!(out, "{}", self.location(cpu, cpu.pc())).unwrap();
+    }
+
+    fn list(&self, cpu: &CPU, start: u16, out: &mut String) {
+        // Wraps around the end of memory like the PC does
+        let mut address = start as usize % cpu.ram().len();
+        for _ in 0..12 {
+            if let Some(label) = self.labels.get(&(address as u16)) {
+                writeln!(out, "{}:", label).unwrap();
+            }
+            let (text, length) = disasm::disassemble_labelled(cpu.ram(), address, &self.labels);
+            let marker = if address == cpu.pc() as usize { '>' } else { ' ' };
+            let breakpoint = if self.breakpoints.iter().any(|breakpoint| breakpoint.address as usize == address) {
+                '*'
+            } else {
+                ' '
+            };
+            writeln!(out, "{}{} 0x{:04X}  {:02X}{:02X}  {}", marker, breakpoint, address, cpu.ram()[address], cpu.ram()[(address + 1) % cpu.ram().len()], text).unwrap();
+            address = (address + length) % cpu.ram().len();
+        }
+    }
+}
+
+
+fn registers(cpu: &CPU, out: &mut String) {
+    writeln!(
+        out,
+        "PC 0x{:04X}  I 0x{:04X}  DT {:3}  ST {:3}",
+        cpu.pc(),
+        cpu.index(),
+        cpu.delay_timer(),
+        cpu.sound_timer()
+    )
+    .unwrap();
+    for (offset, values) in cpu.registers().chunks(8).enumerate() {
+        let line: Vec<String> = values
+            .iter()
+            .enumerate()
+            .map(|(register, value)| format!("V{:X} {:02X}", offset * 8 + register, value))
+            .collect();
+        writeln!(out, "{}", line.join("  ")).unwrap();
+    }
+    let stack: Vec<String> = cpu.stack().iter().map(|address| format!("0x{:04X}", address)).collect();
+    writeln!(out, "Stack ({}): {}", stack.len(), stack.join(" ")).unwrap();
+    let keys: Vec<String> = (0..16).filter(|&key| cpu.keys()[key]).map(|key| format!("{:X}", key)).collect();
+    writeln!(out, "Keys: {}", keys.join(" ")).unwrap();
+}
+
+fn hexdump(cpu: &CPU, address: u16, length: u16, out: &mut String) {
+    let ram = cpu.ram();
+    for row in (0..length as usize).step_by(16) {
+        let start = address as usize + row;
+        let bytes: Vec<String> = (start..start + 16.min(length as usize - row))
+            .map(|at| format!("{:02X}", ram[at % ram.len()]))
+            .collect();
+        writeln!(out, "0x{:04X}: {}", start % ram.len(), bytes.join(" ")).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quirks::Variant;
+
+    #[test]
+    fn lists_addresses_past_the_end_of_memory() {
+        let mut debugger = Debugger::new();
+        let mut cpu = CPU::with_seed(Variant::Vip.quirks(), 0);
+        cpu.load_rom(&[0x12, 0x00]).unwrap();
+        let (out, _) = debugger.execute("l 0x2000", &mut cpu);
+        assert!(out.starts_with("   0x0000  F090"), "{}", out);
+        let (out, _) = debugger.execute("l 0x0FFE", &mut cpu);
+        assert!(out.starts_with("   0x0FFE  0000  ") && out.contains("\n   0x0000  F090"), "{}", out);
+        let (out, _) = debugger.execute("l 0x1200", &mut cpu);
+        assert!(out.starts_with(">  0x0200  1200  JP 0x200"), "{}", out);
+    }
+}
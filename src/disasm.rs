@@ -0,0 +1,378 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::cpu::START_RAM_ADDRESS;
+use crate::instruction::Instruction;
+
+// Names of addresses, e.g. the labels of compiled Octo source
+pub type Labels = BTreeMap<u16, String>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Syntax {
+    // Classic mnemonics from Cowgod's technical reference, extended with the
+    // SCHIP and XO-CHIP opcodes
+    #[default]
+    Cowgod,
+    Octo,
+}
+
+impl Syntax {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "cowgod" => Some(Syntax::Cowgod),
+            "octo" => Some(Syntax::Octo),
+            _ => None,
+        }
+    }
+}
+
+// Renders one instruction; `address` names the targets of jumps, calls and
+// loads of I, e.g. with a label
+pub fn render(instruction: Instruction, syntax: Syntax, address: &dyn Fn(u16) -> String) -> String {
+    match syntax {
+        Syntax::Cowgod => cowgod(instruction, address),
+        Syntax::Octo => octo(instruction, address),
+    }
+}
+
+fn hex_address(address: u16) -> String {
+    format!("0x{:03X}", address)
+}
+
+fn cowgod(instruction: Instruction, address: &dyn Fn(u16) -> String) -> String {
+    use Instruction::*;
+    match instruction {
+        ScrollDown(n) => format!("SCD {}", n),
+        ScrollUp(n) => format!("SCU {}", n),
+        Clear => "CLS".to_string(),
+        Return => "RET".to_string(),
+        ScrollRight => "SCR".to_string(),
+        ScrollLeft => "SCL".to_string(),
+        Exit => "EXIT".to_string(),
+        Lores => "LOW".to_string(),
+        Hires => "HIGH".to_string(),
+        Jump(target) => format!("JP {}", address(target)),
+        Call(target) => format!("CALL {}", address(target)),
+        SkipIfEqual(x, nn) => format!("SE V{:X}, 0x{:02X}", x, nn),
+        SkipIfNotEqual(x, nn) => format!("SNE V{:X}, 0x{:02X}", x, nn),
+        SkipIfRegistersEqual(x, y) => format!("SE V{:X}, V{:X}", x, y),
+        SkipIfRegistersNotEqual(x, y) => format!("SNE V{:X}, V{:X}", x, y),
+        SaveRange(x, y) => format!("SAVE V{:X} - V{:X}", x, y),
+        LoadRange(x, y) => format!("LOAD V{:X} - V{:X}", x, y),
+        Set(x, nn) => format!("LD V{:X}, 0x{:02X}", x, nn),
+        Add(x, nn) => format!("ADD V{:X}, 0x{:02X}", x, nn),
+        Copy(x, y) => format!("LD V{:X}, V{:X}", x, y),
+        Or(x, y) => format!("OR V{:X}, V{:X}", x, y),
+        And(x, y) => format!("AND V{:X}, V{:X}", x, y),
+        Xor(x, y) => format!("XOR V{:X}, V{:X}", x, y),
+        AddRegister(x, y) => format!("ADD V{:X}, V{:X}", x, y),
+        Subtract(x, y) => format!("SUB V{:X}, V{:X}", x, y),
+        ShiftRight(x, y) => format!("SHR V{:X}, V{:X}", x, y),
+        SubtractReversed(x, y) => format!("SUBN V{:X}, V{:X}", x, y),
+        ShiftLeft(x, y) => format!("SHL V{:X}, V{:X}", x, y),
+        SetIndex(target) => format!("LD I, {}", address(target)),
+        JumpOffset(target) => format!("JP V0, {}", address(target)),
+        Random(x, nn) => format!("RND V{:X}, 0x{:02X}", x, nn),
+        Draw(x, y, n) => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        SkipIfKey(x) => format!("SKP V{:X}", x),
+        SkipIfNotKey(x) => format!("SKNP V{:X}", x),
+        SetIndexLong(target) => format!("LD I, LONG {}", address(target)),
+        Plane(n) => format!("PLANE {}", n),
+        Audio => "AUDIO".to_string(),
+        GetDelay(x) => format!("LD V{:X}, DT", x),
+        WaitKey(x) => format!("LD V{:X}, K", x),
+        SetDelay(x) => format!("LD DT, V{:X}", x),
+        SetSound(x) => format!("LD ST, V{:X}", x),
+        AddIndex(x) => format!("ADD I, V{:X}", x),
+        Font(x) => format!("LD F, V{:X}", x),
+        BigFont(x) => format!("LD HF, V{:X}", x),
+        Bcd(x) => format!("LD B, V{:X}", x),
+        Pitch(x) => format!("PITCH V{:X}", x),
+        Store(x) => format!("LD [I], V{:X}", x),
+        Load(x) => format!("LD V{:X}, [I]", x),
+        SaveFlags(x) => format!("LD R, V{:X}", x),
+        LoadFlags(x) => format!("LD V{:X}, R", x),
+        Unknown(word) => format!("DW 0x{:04X}", word),
+    }
+}
+
+// Octo has no skip instructions, only `if ... then` which skips when the
+// condition is false, so the conditions below are the opposite of the opcode
+fn octo(instruction: Instruction, address: &dyn Fn(u16) -> String) -> String {
+    use Instruction::*;
+    match instruction {
+        ScrollDown(n) => format!("scroll-down {}", n),
+        ScrollUp(n) => format!("scroll-up {}", n),
+        Clear => "clear".to_string(),
+        Return => "return".to_string(),
+        ScrollRight => "scroll-right".to_string(),
+        ScrollLeft => "scroll-left".to_string(),
+        Exit => "exit".to_string(),
+        Lores => "lores".to_string(),
+        Hires => "hires".to_string(),
+        Jump(target) => format!("jump {}", address(target)),
+        Call(target) => format!(":call {}", address(target)),
+        SkipIfEqual(x, nn) => format!("if v{:x} != 0x{:02X} then", x, nn),
+        SkipIfNotEqual(x, nn) => format!("if v{:x} == 0x{:02X} then", x, nn),
+        SkipIfRegistersEqual(x, y) => format!("if v{:x} != v{:x} then", x, y),
+        SkipIfRegistersNotEqual(x, y) => format!("if v{:x} == v{:x} then", x, y),
+        SaveRange(x, y) => format!("save v{:x} - v{:x}", x, y),
+        LoadRange(x, y) => format!("load v{:x} - v{:x}", x, y),
+        Set(x, nn) => format!("v{:x} := 0x{:02X}", x, nn),
+        Add(x, nn) => format!("v{:x} += 0x{:02X}", x, nn),
+        Copy(x, y) => format!("v{:x} := v{:x}", x, y),
+        Or(x, y) => format!("v{:x} |= v{:x}", x, y),
+        And(x, y) => format!("v{:x} &= v{:x}", x, y),
+        Xor(x, y) => format!("v{:x} ^= v{:x}", x, y),
+        AddRegister(x, y) => format!("v{:x} += v{:x}", x, y),
+        Subtract(x, y) => format!("v{:x} -= v{:x}", x, y),
+        ShiftRight(x, y) => format!("v{:x} >>= v{:x}", x, y),
+        SubtractReversed(x, y) => format!("v{:x} =- v{:x}", x, y),
+        ShiftLeft(x, y) => format!("v{:x} <<= v{:x}", x, y),
+        SetIndex(target) => format!("i := {}", address(target)),
+        JumpOffset(target) => format!("jump0 {}", address(target)),
+        Random(x, nn) => format!("v{:x} := random 0x{:02X}", x, nn),
+        Draw(x, y, n) => format!("sprite v{:x} v{:x} {}", x, y, n),
+        SkipIfKey(x) => format!("if v{:x} -key then", x),
+        SkipIfNotKey(x) => format!("if v{:x} key then", x),
+        SetIndexLong(target) => format!("i := long {}", address(target)),
+        Plane(n) => format!("plane {}", n),
+        Audio => "audio".to_string(),
+        GetDelay(x) => format!("v{:x} := delay", x),
+        WaitKey(x) => format!("v{:x} := key", x),
+        SetDelay(x) => format!("delay := v{:x}", x),
+        SetSound(x) => format!("buzzer := v{:x}", x),
+        AddIndex(x) => format!("i += v{:x}", x),
+        Font(x) => format!("i := hex v{:x}", x),
+        BigFont(x) => format!("i := bighex v{:x}", x),
+        Bcd(x) => format!("bcd v{:x}", x),
+        Pitch(x) => format!("pitch := v{:x}", x),
+        Store(x) => format!("save v{:x}", x),
+        Load(x) => format!("load v{:x}", x),
+        SaveFlags(x) => format!("saveflags v{:x}", x),
+        LoadFlags(x) => format!("loadflags v{:x}", x),
+        Unknown(word) => format!("0x{:02X} 0x{:02X}", word >> 8, word & 0xFF),
+    }
+}
+
+fn word(bytes: &[u8], at: usize) -> u16 {
+    (bytes[at % bytes.len()] as u16) << 8 | bytes[(at + 1) % bytes.len()] as u16
+}
+
+// Decodes the instruction at `address` of a memory image
+pub fn decode_at(ram: &[u8], address: usize) -> Instruction {
+    Instruction::decode(word(ram, address), word(ram, address + 2))
+}
+
+// Disassembles the instruction at `address` in Cowgod syntax, returning its
+// text and size in bytes
+pub fn disassemble(ram: &[u8], address: usize) -> (String, usize) {
+    disassemble_labelled(ram, address, &Labels::new())
+}
+
+// Same as `disassemble`, naming the targets found in `labels`
+pub fn disassemble_labelled(ram: &[u8], address: usize, labels: &Labels) -> (String, usize) {
+    let instruction = decode_at(ram, address);
+    let name = |target: u16| labels.get(&target).cloned().unwrap_or_else(|| hex_address(target));
+    (render(instruction, Syntax::Cowgod, &name), instruction.size())
+}
+
+// Cowgod mnemonic of a single word instruction
+pub fn mnemonic(opcode: u16) -> String {
+    render(Instruction::decode(opcode, 0), Syntax::Cowgod, &hex_address)
+}
+
+// What a label marks, from the most to the least telling
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LabelKind {
+    Entry,
+    Subroutine,
+    JumpTable,
+    Code,
+    // Drawn by DXYN, with its height in rows (0 for a 16x16 sprite)
+    Sprite(u8),
+    Data,
+}
+
+#[derive(Debug)]
+pub struct Analysis {
+    // Instruction found at each byte of the ROM, where one starts
+    instructions: Vec<Option<Instruction>>,
+    labels: BTreeMap<u16, LabelKind>,
+    rom: Vec<u8>,
+}
+
+impl Analysis {
+    pub fn labels(&self) -> &BTreeMap<u16, LabelKind> {
+        &self.labels
+    }
+
+    pub fn is_code(&self, address: u16) -> bool {
+        self.offset(address).is_some_and(|offset| self.instructions[offset].is_some())
+    }
+
+    fn offset(&self, address: u16) -> Option<usize> {
+        (address as usize).checked_sub(START_RAM_ADDRESS).filter(|&offset| offset < self.rom.len())
+    }
+
+    pub fn label_name(&self, address: u16) -> Option<String> {
+        let kind = self.labels.get(&address)?;
+        let prefix = match kind {
+            LabelKind::Entry => return Some("main".to_string()),
+            LabelKind::Subroutine => "sub",
+            LabelKind::JumpTable => "table",
+            LabelKind::Code => "label",
+            LabelKind::Sprite(_) => "sprite",
+            LabelKind::Data => "data",
+        };
+        Some(format!("{}_{:03X}", prefix, address))
+    }
+
+    fn mark(&mut self, address: u16, kind: LabelKind) {
+        if self.offset(address).is_some() {
+            let label = self.labels.entry(address).or_insert(kind);
+            *label = (*label).min(kind);
+        }
+    }
+}
+
+// Follows every jump, call and skip from 0x200 to find the instructions of a
+// ROM; whatever isn't reached is data. Targets of I are labelled as data, or
+// as sprites when a DXYN draws them before I changes.
+pub fn analyze(rom: &[u8]) -> Analysis {
+    let mut analysis = Analysis {
+        instructions: vec![None; rom.len()],
+        labels: BTreeMap::new(),
+        rom: rom.to_vec(),
+    };
+    if rom.is_empty() {
+        return analysis;
+    }
+    let start = START_RAM_ADDRESS as u16;
+    analysis.mark(start, LabelKind::Entry);
+    let mut pending = vec![start];
+    while let Some(mut address) = pending.pop() {
+        // Last address loaded into I along this path
+        let mut index = None;
+        while let Some(offset) = analysis.offset(address) {
+            if analysis.instructions[offset].is_some() {
+                break;
+            }
+            let instruction = Instruction::decode(word(rom, offset), word(rom, offset + 2));
+            if let Instruction::Unknown(_) = instruction {
+                break;
+            }
+            analysis.instructions[offset] = Some(instruction);
+            let next = address.wrapping_add(instruction.size() as u16);
+            match instruction {
+                Instruction::Jump(target) => {
+                    analysis.mark(target, LabelKind::Code);
+                    pending.push(target);
+                },
+                Instruction::Call(target) => {
+                    analysis.mark(target, LabelKind::Subroutine);
+                    pending.push(target);
+                },
+                Instruction::JumpOffset(target) => {
+                    analysis.mark(target, LabelKind::JumpTable);
+                    pending.push(target);
+                },
+                Instruction::SetIndex(target) | Instruction::SetIndexLong(target) => {
+                    analysis.mark(target, LabelKind::Data);
+                    index = Some(target);
+                },
+                Instruction::Draw(_, _, rows) => {
+                    if let Some(target) = index {
+                        analysis.mark(target, LabelKind::Sprite(rows));
+                    }
+                },
+                Instruction::AddIndex(_) | Instruction::Font(_) | Instruction::BigFont(_) => index = None,
+                _ => (),
+            }
+            if instruction.is_skip() {
+                let skipped = analysis.offset(next).map_or(2, |offset| Instruction::decode(word(rom, offset), 0).size());
+                pending.push(next.wrapping_add(skipped as u16));
+            }
+            if instruction.ends_block() {
+                break;
+            }
+            address = next;
+        }
+    }
+    analysis
+}
+
+// Disassembles a whole ROM into source the assembler accepts, with labels
+// for subroutines, jump targets and data, and the address and bytes of each
+// line in a comment
+pub fn listing(rom: &[u8], syntax: Syntax) -> String {
+    let analysis = analyze(rom);
+    let name = |address: u16| analysis.label_name(address).unwrap_or_else(|| hex_address(address));
+    let comment = match syntax {
+        Syntax::Cowgod => ';',
+        Syntax::Octo => '#',
+    };
+    let mut out = String::new();
+    let mut offset = 0;
+    while offset < rom.len() {
+        let address = (START_RAM_ADDRESS + offset) as u16;
+        if let Some(label) = analysis.label_name(address) {
+            match syntax {
+                Syntax::Cowgod => writeln!(out, "{}:", label).unwrap(),
+                Syntax::Octo => writeln!(out, ": {}", label).unwrap(),
+            }
+        }
+        let instruction = analysis.instructions[offset].filter(|instruction| {
+            // Instructions cut by a label, or with ignored bits that wouldn't
+            // assemble back to the same opcode, are spelled out as data
+            offset + instruction.size() <= rom.len()
+                && (1..instruction.size() as u16).all(|inside| !analysis.labels.contains_key(&(address + inside)))
+                && instruction.encode().iter().flat_map(|word| word.to_be_bytes()).eq(rom[offset..offset + instruction.size()].iter().copied())
+        });
+        if let Some(instruction) = instruction {
+            let bytes = &rom[offset..offset + instruction.size()];
+            let text = render(instruction, syntax, &name);
+            writeln!(out, "    {:<24} {} 0x{:03X}  {}", text, comment, address, hex_bytes(bytes)).unwrap();
+            offset += instruction.size();
+            continue;
+        }
+        // Data runs until the next label or instruction
+        let end = (offset + 1..rom.len())
+            .find(|&end| {
+                let address = (START_RAM_ADDRESS + end) as u16;
+                analysis.labels.contains_key(&address) || analysis.instructions[end].is_some()
+            })
+            .unwrap_or(rom.len());
+        let row = match analysis.labels.get(&address) {
+            Some(LabelKind::Sprite(0)) => 2,
+            Some(LabelKind::Sprite(_)) => 1,
+            _ => 8,
+        };
+        for (index, chunk) in rom[offset..end].chunks(row).enumerate() {
+            let at = address as usize + index * row;
+            if row <= 2 {
+                // Sprites read better as pixels
+                let pixels: Vec<String> = chunk.iter().map(|byte| format!("0b{:08b}", byte)).collect();
+                let text = data(&pixels, syntax);
+                let art: String = chunk.iter().map(|byte| format!("{:08b}", byte)).collect::<String>().replace('0', ".").replace('1', "#");
+                writeln!(out, "    {:<24} {} 0x{:03X}  {}", text, comment, at, art).unwrap();
+            } else {
+                let values: Vec<String> = chunk.iter().map(|byte| format!("0x{:02X}", byte)).collect();
+                writeln!(out, "    {:<24} {} 0x{:03X}", data(&values, syntax), comment, at).unwrap();
+            }
+        }
+        offset = end;
+    }
+    out
+}
+
+fn data(values: &[String], syntax: Syntax) -> String {
+    match syntax {
+        Syntax::Cowgod => format!("DB {}", values.join(", ")),
+        Syntax::Octo => values.join(" "),
+    }
+}
+
+fn hex_bytes(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
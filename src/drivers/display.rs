@@ -1,30 +1,33 @@
 use sdl2::{video::Window, render::Canvas, pixels};
 
+use chip8_emulator::cpu::{Framebuffer, LORES_WIDTH, LORES_HEIGHT};
+use chip8_emulator::error::{Error, Result};
+use chip8_emulator::frontend::VideoSink;
+use chip8_emulator::palette::Palette;
+
 extern crate sdl2;
 
 pub struct DisplayDriver {
     canvas: Canvas<Window>,
+    pixel_size: u32,
+    palette: Palette,
 }
 
-pub const WIDTH: u32 = 64;
-pub const HEIGHT: u32 = 32;
-const PIXEL_SIZE: u32 = 10;
-
 impl DisplayDriver {
-    pub fn new(sdl_context: &sdl2::Sdl) -> Result<Self, String>  {
-        let video_subsys = sdl_context.video().unwrap();
+    pub fn new(sdl_context: &sdl2::Sdl, pixel_size: u32, palette: Palette) -> Result<Self>  {
+        let video_subsys = sdl_context.video().map_err(Error::Backend)?;
         let window = video_subsys
             .window(
                 "Chip8 Emulator",
-                WIDTH * PIXEL_SIZE,
-                HEIGHT * PIXEL_SIZE,
+                LORES_WIDTH * pixel_size,
+                LORES_HEIGHT * pixel_size,
             )
             .position(0, 0)
             .opengl()
             .build()
-            .unwrap();
+            .map_err(|e| Error::Backend(e.to_string()))?;
 
-        let mut canvas = window.into_canvas().build().unwrap();
+        let mut canvas = window.into_canvas().build().map_err(|e| Error::Backend(e.to_string()))?;
 
         canvas.set_draw_color(pixels::Color::RGB(0, 0, 0));
         canvas.clear();
@@ -32,22 +35,40 @@ impl DisplayDriver {
 
         Ok(Self {
             canvas,
+            pixel_size,
+            palette,
         })
     }
 
-    pub fn draw(&mut self, vram: &[[bool; WIDTH as usize]; HEIGHT as usize]) -> Result<(), String> {
-        self.canvas.set_draw_color(sdl2::pixels::Color::WHITE);
+    fn color(&self, pixel: u8) -> pixels::Color {
+        let color = self.palette.color(pixel);
+        pixels::Color::RGB(color.r, color.g, color.b)
+    }
+}
+
+impl VideoSink for DisplayDriver {
+    fn draw(&mut self, vram: &Framebuffer, width: usize, height: usize) -> Result<()> {
+        // Keep the window size fixed and scale pixels to the active
+        // resolution. Each pixel's edges come from the window size, so odd
+        // scales in hires mode still fill the window, some pixels a window
+        // pixel wider than others; at scale 1 SDL widens empty rects to one
+        // window pixel.
+        let (window_width, window_height) = (LORES_WIDTH * self.pixel_size, LORES_HEIGHT * self.pixel_size);
+        let edge = |index: usize, count: usize, size: u32| (index as u32 * size / count as u32) as i32;
+        self.canvas.set_draw_color(self.color(0));
         self.canvas.clear();
-        self.canvas.set_draw_color(sdl2::pixels::Color::BLACK);
-        for y in 0..HEIGHT {
-            for x in 0..WIDTH {
-                if vram[y as usize][x as usize] {
+        for (y, row) in vram.iter().enumerate().take(height) {
+            let (top, bottom) = (edge(y, height, window_height), edge(y + 1, height, window_height));
+            for (x, &pixel) in row.iter().enumerate().take(width) {
+                if pixel != 0 {
+                    let (left, right) = (edge(x, width, window_width), edge(x + 1, width, window_width));
+                    self.canvas.set_draw_color(self.color(pixel));
                     self.canvas.fill_rect(sdl2::rect::Rect::new(
-                        x as i32 * PIXEL_SIZE as i32,
-                        y as i32 * PIXEL_SIZE as i32,
-                        PIXEL_SIZE,
-                        PIXEL_SIZE,
-                    ))?;
+                        left,
+                        top,
+                        (right - left) as u32,
+                        (bottom - top) as u32,
+                    )).map_err(Error::Backend)?;
                 }
             }
         }
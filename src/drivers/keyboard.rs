@@ -1,71 +1,133 @@
-use sdl2::{EventPump, keyboard::Keycode, event::Event};
+use sdl2::{EventPump, keyboard::{Keycode, Mod}, event::{Event, WindowEvent}};
 
+use chip8_emulator::error::{Error, Result};
+use chip8_emulator::frontend::{Command, InputSource};
 
 pub struct KeyboardDriver {
+    event_pump: EventPump,
+    rewinding: bool,
 }
 
-#[derive(Debug, PartialEq)]
-pub enum Result {
-    Continue,
-    Quit
+impl KeyboardDriver {
+    pub fn new(context: &sdl2::Sdl) -> Result<Self> {
+        Ok(Self {
+            event_pump: context.event_pump().map_err(Error::Backend)?,
+            rewinding: false,
+        })
+    }
 }
 
-impl KeyboardDriver {
-    pub fn new() -> Self {
-        Self {}
+fn keypad_code(keycode: Keycode) -> Option<u8> {
+    match keycode {
+        Keycode::Num1 => Some(0x1),
+        Keycode::Num2 => Some(0x2),
+        Keycode::Num3 => Some(0x3),
+        Keycode::Num4 => Some(0xc),
+        Keycode::A => Some(0x4),
+        Keycode::Z => Some(0x5),
+        Keycode::E => Some(0x6),
+        Keycode::R => Some(0xD),
+        Keycode::Q => Some(0x7),
+        Keycode::S => Some(0x8),
+        Keycode::D => Some(0x9),
+        Keycode::F => Some(0xE),
+        Keycode::W => Some(0xA),
+        Keycode::X => Some(0x0),
+        Keycode::C => Some(0xB),
+        Keycode::V => Some(0xF),
+        _ => None
+    }
+}
+
+fn state_slot(keycode: Keycode) -> Option<u8> {
+    match keycode {
+        Keycode::F1 => Some(1),
+        Keycode::F2 => Some(2),
+        Keycode::F3 => Some(3),
+        Keycode::F4 => Some(4),
+        Keycode::F5 => Some(5),
+        Keycode::F6 => Some(6),
+        Keycode::F7 => Some(7),
+        Keycode::F8 => Some(8),
+        Keycode::F9 => Some(9),
+        _ => None
     }
+}
 
-    pub fn keys_pressed(&self, event_pump: &mut EventPump, keys_buffer: &mut [bool; 16]) -> Result {
-        self.clear_buffer(keys_buffer);
-        for event in event_pump.poll_iter() {
+// Keys stay pressed in `keys_buffer` from their KeyDown until their KeyUp
+impl InputSource for KeyboardDriver {
+    fn poll(&mut self, keys_buffer: &mut [bool; 16]) -> Command {
+        for event in self.event_pump.poll_iter() {
             match event {
                 Event::Quit { .. }
                 | Event::KeyDown {
                     keycode: Some(Keycode::Escape),
                     ..
-                } => return  Result::Quit,
+                } => return Command::Quit,
+                Event::KeyDown {
+                    keycode: Some(Keycode::P),
+                    repeat: false,
+                    ..
+                } => return Command::TogglePause,
+                Event::KeyDown {
+                    keycode: Some(Keycode::F12),
+                    repeat: false,
+                    ..
+                } => return Command::Screenshot,
+                Event::KeyDown {
+                    keycode: Some(Keycode::F11),
+                    repeat: false,
+                    ..
+                } => return Command::ToggleClip,
                 Event::KeyDown {
                     keycode: Some(keycode),
+                    keymod,
+                    repeat,
                     ..
                 } => {
-                    let code: Option<u8> = match keycode {
-                        Keycode::Num1 => Some(0x1),
-                        Keycode::Num2 => Some(0x2),
-                        Keycode::Num3 => Some(0x3),
-                        Keycode::Num4 => Some(0xc),
-                        Keycode::A => Some(0x4),
-                        Keycode::Z => Some(0x5),
-                        Keycode::E => Some(0x6),
-                        Keycode::R => Some(0xD),
-                        Keycode::Q => Some(0x7),
-                        Keycode::S => Some(0x8),
-                        Keycode::D => Some(0x9),
-                        Keycode::F => Some(0xE),
-                        Keycode::W => Some(0xA),
-                        Keycode::X => Some(0x0),
-                        Keycode::C => Some(0xB),
-                        Keycode::V => Some(0xF),
-                        _ => None
-                    };
-                    if let Some(code) = code {
+                    if keycode == Keycode::Backspace {
+                        self.rewinding = true;
+                        continue;
+                    }
+                    // F1-F9 load a save state slot, Shift+F1-F9 save it
+                    if let Some(slot) = state_slot(keycode) {
+                        if repeat {
+                            continue;
+                        }
+                        if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                            return Command::SaveState(slot);
+                        }
+                        return Command::LoadState(slot);
+                    }
+                    if let Some(code) = keypad_code(keycode) {
                         keys_buffer[code as usize] = true;
                     }
                 },
+                Event::KeyUp {
+                    keycode: Some(keycode),
+                    ..
+                } => {
+                    if keycode == Keycode::Backspace {
+                        self.rewinding = false;
+                    }
+                    if let Some(code) = keypad_code(keycode) {
+                        keys_buffer[code as usize] = false;
+                    }
+                },
+                // Keys released while the window was unfocused never send a KeyUp
+                Event::Window {
+                    win_event: WindowEvent::FocusLost,
+                    ..
+                } => {
+                    *keys_buffer = [false; 16];
+                    self.rewinding = false;
+                },
                 _ => {}
             }
         }
-        Result::Continue
-    }
-
-    pub fn clear_buffer(&self, keys_buffer: &mut [bool; 16]) {
-        (0..keys_buffer.len()).for_each(|i| {
-            keys_buffer[i] = false;
-        });
+        if self.rewinding {
+            return Command::Rewind;
+        }
+        Command::Continue
     }
 }
-
-impl Default for KeyboardDriver {
-    fn default() -> Self {
-        Self::new()
-    }
-}
\ No newline at end of file
@@ -1,3 +1,63 @@
+use chip8_emulator::buzzer::Tone;
+use chip8_emulator::cpu::CPU;
+use chip8_emulator::emulator::Emulator;
+use chip8_emulator::error::{Error, Result};
+use chip8_emulator::frontend::wav::{WavAudio, WavFile};
+use chip8_emulator::frontend::InputSource;
+use chip8_emulator::palette::Palette;
+
 pub mod display;
 pub mod keyboard;
-pub mod sound;
\ No newline at end of file
+pub mod sound;
+
+pub type SdlEmulator<I = keyboard::KeyboardDriver> = Emulator<display::DisplayDriver, (Option<sound::SoundDriver>, Option<WavFile>), I>;
+
+pub struct Settings {
+    pub scale: u32,
+    pub palette: Palette,
+    pub mute: bool,
+    pub tone: Tone,
+    // Also records the sound into this WAV file
+    pub wav: Option<String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            scale: 10,
+            palette: Palette::default(),
+            mute: false,
+            tone: Tone::default(),
+            wav: None,
+        }
+    }
+}
+
+pub fn emulator(cpu: CPU, settings: &Settings) -> Result<SdlEmulator> {
+    emulator_with_input(cpu, settings, |keyboard| keyboard)
+}
+
+// Builds the SDL frontend with the keyboard wrapped by another input source,
+// e.g. a movie player
+pub fn emulator_with_input<I: InputSource>(
+    cpu: CPU,
+    settings: &Settings,
+    input: impl FnOnce(keyboard::KeyboardDriver) -> I,
+) -> Result<SdlEmulator<I>> {
+    let context = sdl2::init().map_err(Error::Backend)?;
+    let display_driver = display::DisplayDriver::new(&context, settings.scale, settings.palette)?;
+    // Muting skips opening the audio device altogether
+    let sound_driver = if settings.mute {
+        None
+    } else {
+        Some(sound::SoundDriver::new(&context, settings.tone)?)
+    };
+    let wav = settings.wav.as_deref().map(|path| WavAudio::create(path, settings.tone)).transpose()?;
+
+    Ok(Emulator::new(
+        cpu,
+        display_driver,
+        (sound_driver, wav),
+        input(keyboard::KeyboardDriver::new(&context)?),
+    ))
+}
@@ -1,68 +1,73 @@
 use sdl2::audio::{AudioCallback, AudioSpecDesired};
 
+use chip8_emulator::buzzer::{Buzzer, Tone, SAMPLE_RATE};
+use chip8_emulator::error::{Error, Result};
+use chip8_emulator::frontend::AudioSink;
+
 pub struct SoundDriver {
-    device: sdl2::audio::AudioDevice<SquareWave>,
+    device: sdl2::audio::AudioDevice<Callback>,
     sound_on: bool,
-} 
-struct SquareWave {
-    phase_inc: f32,
-    phase: f32,
-    volume: f32
+    pattern: Option<[u8; 16]>,
+    pitch: u8,
 }
 
-impl AudioCallback for SquareWave {
+struct Callback(Buzzer);
+
+impl AudioCallback for Callback {
     type Channel = f32;
 
     fn callback(&mut self, out: &mut [f32]) {
-        // Generate a square wave
-        for x in out.iter_mut() {
-            *x = if self.phase <= 0.5 {
-                self.volume
-            } else {
-                -self.volume
-            };
-            self.phase = (self.phase + self.phase_inc) % 1.0;
-        }
+        self.0.fill(out);
     }
 }
 
-impl SoundDriver {
-    pub fn start(&mut self) {
+impl AudioSink for SoundDriver {
+    fn start(&mut self) {
         if !self.sound_on {
             self.sound_on = true;
-            self.device.resume();    
+            self.device.lock().0.set_gate(true);
         }
     }
 
-    pub fn stop(&mut self) {
+    fn stop(&mut self) {
         if self.sound_on {
-            self.device.pause();
             self.sound_on = false;
+            self.device.lock().0.set_gate(false);
         }
     }
 
-    pub fn new(context: &sdl2::Sdl) -> Self {
-        let audio_subsystem = context.audio().unwrap();
+    fn set_pattern(&mut self, pattern: Option<[u8; 16]>, pitch: u8) {
+        if self.pattern == pattern && self.pitch == pitch {
+            return;
+        }
+        self.pattern = pattern;
+        self.pitch = pitch;
+        self.device.lock().0.set_pattern(pattern, pitch);
+    }
+}
+
+impl SoundDriver {
+    pub fn new(context: &sdl2::Sdl, tone: Tone) -> Result<Self> {
+        let audio_subsystem = context.audio().map_err(Error::Backend)?;
 
         let desired_spec = AudioSpecDesired {
-            freq: Some(44100),
+            freq: Some(SAMPLE_RATE as i32),
             channels: Some(1),  // mono
             samples: None       // default sample size
         };
 
         let device = audio_subsystem.open_playback(None, &desired_spec, |spec| {
-            // initialize the audio callback
-            SquareWave {
-                phase_inc: 440.0 / spec.freq as f32,
-                phase: 0.0,
-                volume: 0.25
-            }
-        }).unwrap();
+            Callback(Buzzer::new(spec.freq as u32, tone))
+        }).map_err(Error::Backend)?;
+        // The device always plays so the release ramp can fade out; the
+        // buzzer's gate decides what is heard
+        device.resume();
 
-        Self { 
+        Ok(Self {
             device,
-            sound_on: false
-        }
+            sound_on: false,
+            pattern: None,
+            pitch: 64,
+        })
     }
 }
-
@@ -1,53 +1,433 @@
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+use crate::clip::Clip;
 use crate::cpu::CPU;
-use crate::drivers::{*, self};
+use crate::debugger::{Action, Debugger};
+use crate::disasm::Labels;
+use crate::error::Result;
+use crate::frontend::{AudioSink, Command, InputSource, VideoSink};
+use crate::movie::{self, Movie};
+use crate::octo;
+use crate::palette::Palette;
+use crate::rewind::Rewind;
+use crate::screenshot;
+use crate::snapshot;
+use crate::watch::{Hit, WatchAction};
 
-pub const START_RAM_ADDRESS: usize = 0x200;
-pub const FONT_OFFSET: usize = 0x00;
-pub struct Emulator {
+const FRAME_TIME: Duration = Duration::from_micros(1_000_000 / 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ClockMode {
+    // Timers tick whenever 1/60 s of host time has passed
+    #[default]
+    WallClock,
+    // Timers tick exactly once per frame, after its instructions ran, so a
+    // run only depends on the ROM and its input
+    Emulated,
+}
+
+pub struct Emulator<V: VideoSink, A: AudioSink, I: InputSource> {
     cpu: CPU,
-    context: sdl2::Sdl,
-    display_driver: display::DisplayDriver,
-    keyboard_driver: keyboard::KeyboardDriver,
-    sound_driver: sound::SoundDriver,
+    video: V,
+    audio: A,
+    input: I,
+    instructions_per_frame: u32,
+    paused: bool,
+    clock: ClockMode,
+    last_timer_tick: Instant,
+    rom_path: Option<String>,
+    // Labels of the program when it was compiled from Octo source
+    labels: Labels,
+    rewind: Rewind,
+    recording: Option<Movie>,
+    // Set once the debugger broke in or a state was loaded; the movie keeps
+    // the frames before
+    recording_ended: bool,
+    frames: u64,
+    debugger: Option<Debugger>,
+    // Colors and pixel size of screenshots and clips
+    palette: Palette,
+    screenshot_scale: u32,
+    clip: Option<Clip>,
 }
 
-impl Emulator {
-    pub fn new() -> Result<Emulator, String> {
-        let context = sdl2::init()?;
-        let display_driver = display::DisplayDriver::new(&context)?;
-        
-        Ok(Emulator {
-            cpu: CPU::default(),
-            display_driver,
-            sound_driver: sound::SoundDriver::new(&context),
-            context,
-            keyboard_driver: keyboard::KeyboardDriver::new(),
-        })
-    }
-
-    pub fn load_program(&mut self, path: &str) -> Result<(), String> {
-        self.cpu.load_ram(path)
-    }
-
-    pub fn run(&mut self) -> Result<(), String> {
-        let mut event_pump = self.context.event_pump()?;
-    
-         loop {
-            ::std::thread::sleep(Duration::from_micros(600));
-            self.cpu.timer.update();
-            self.cpu.sound_timer.update();
-            if !self.cpu.sound_timer.is_zero() {
-                self.sound_driver.start();
-            } else {
-                self.sound_driver.stop();
-            }
-            if self.keyboard_driver.keys_pressed(&mut event_pump, &mut  self.cpu.key_buffer) == drivers::keyboard::Result::Quit {
+impl<V: VideoSink, A: AudioSink, I: InputSource> Emulator<V, A, I> {
+    pub fn new(cpu: CPU, video: V, audio: A, input: I) -> Self {
+        Emulator {
+            cpu,
+            video,
+            audio,
+            input,
+            instructions_per_frame: 15,
+            paused: false,
+            clock: ClockMode::default(),
+            last_timer_tick: Instant::now(),
+            rom_path: None,
+            labels: Labels::new(),
+            rewind: Rewind::new(0),
+            recording: None,
+            recording_ended: false,
+            frames: 0,
+            debugger: None,
+            palette: Palette::default(),
+            screenshot_scale: 1,
+            clip: None,
+        }
+    }
+
+    pub fn cpu(&self) -> &CPU {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut CPU {
+        &mut self.cpu
+    }
+
+    pub fn video(&self) -> &V {
+        &self.video
+    }
+
+    pub fn audio(&self) -> &A {
+        &self.audio
+    }
+
+    pub fn input(&self) -> &I {
+        &self.input
+    }
+
+    // Lets the audio sink write out what it buffered, e.g. a WAV header
+    pub fn finish_audio(&mut self) -> Result<()> {
+        self.audio.finish()
+    }
+
+    pub fn into_parts(self) -> (CPU, V, A, I) {
+        (self.cpu, self.video, self.audio, self.input)
+    }
+
+    pub fn set_instructions_per_frame(&mut self, instructions_per_frame: u32) {
+        self.instructions_per_frame = instructions_per_frame;
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    // Keeps one state per frame for the last `seconds` seconds
+    pub fn set_rewind_seconds(&mut self, seconds: u32) {
+        self.rewind = Rewind::new(seconds as usize * 60);
+    }
+
+    pub fn rewind_buffer(&self) -> &Rewind {
+        &self.rewind
+    }
+
+    // Steps back one frame, keeping the keys currently held
+    fn rewind_frame(&mut self) -> Result<()> {
+        if let Some(state) = self.rewind.pop() {
+            let keys = *self.cpu.keys();
+            self.cpu.load_state(&state)?;
+            *self.cpu.keys_mut() = keys;
+            if let Some(movie) = self.recording.as_mut().filter(|_| !self.recording_ended) {
+                movie.frames.pop();
+            }
+            self.frames = self.frames.saturating_sub(1);
+        }
+        Ok(())
+    }
+
+    // Records the keys of every frame from now on; start before the first
+    // frame so the movie replays from power-on
+    pub fn start_recording(&mut self) {
+        self.clock = ClockMode::Emulated;
+        self.recording = Some(Movie {
+            rom_hash: self.cpu.rom_hash(),
+            quirks: self.cpu.quirks(),
+            seed: self.cpu.seed(),
+            instructions_per_frame: self.instructions_per_frame,
+            frames: Vec::new(),
+        });
+        self.recording_ended = false;
+    }
+
+    // Instructions the debugger runs, or a frame it cuts short, can't be
+    // replayed from keys alone, so the movie ends before the frame the
+    // debugger broke into
+    fn end_recording_for_debugger(&mut self) {
+        if let Some(movie) = self.recording.as_ref().filter(|_| !self.recording_ended) {
+            eprintln!("The debugger broke in, movie recording stopped after {} frames", movie.frames.len());
+            self.recording_ended = true;
+        }
+    }
+
+    pub fn take_recording(&mut self) -> Option<Movie> {
+        self.recording.take()
+    }
+
+    // Number of frames executed so far
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    pub fn set_debugger(&mut self, debugger: Debugger) {
+        self.debugger = Some(debugger);
+    }
+
+    pub fn debugger(&self) -> Option<&Debugger> {
+        self.debugger.as_ref()
+    }
+
+    pub fn debugger_mut(&mut self) -> Option<&mut Debugger> {
+        self.debugger.as_mut()
+    }
+
+    // Logs the watchpoint hits of the last instruction and hands the ones that
+    // break to the debugger, returning whether it paused
+    fn report_watch_hits(&mut self) -> bool {
+        let hits = self.cpu.watchpoints_mut().take_hits();
+        let mut breaks: Vec<Hit> = Vec::new();
+        for hit in hits {
+            match (hit.action, &self.debugger) {
+                (WatchAction::Break, Some(_)) => breaks.push(hit),
+                _ => eprintln!("{}", hit),
+            }
+        }
+        match &mut self.debugger {
+            Some(debugger) if !breaks.is_empty() => {
+                debugger.report_watch_hits(&self.cpu, &breaks);
+                true
+            },
+            _ => false,
+        }
+    }
+
+    pub fn set_clock_mode(&mut self, clock: ClockMode) {
+        self.clock = clock;
+    }
+
+    pub fn clock_mode(&self) -> ClockMode {
+        self.clock
+    }
+
+    fn update_wall_clock_timers(&mut self) {
+        let now = Instant::now();
+        if now.duration_since(self.last_timer_tick) >= FRAME_TIME {
+            self.cpu.tick_timers();
+            self.last_timer_tick = now;
+        }
+    }
+
+    // Loads a ROM, or compiles Octo source given as a .8o file
+    pub fn load_program(&mut self, path: &str) -> Result<()> {
+        if path.ends_with(".8o") {
+            let program = octo::compile_file(path)?;
+            self.cpu.load_rom(&program.rom)?;
+            self.labels = program.labels;
+        } else {
+            self.cpu.load_ram(path)?;
+            self.labels.clear();
+        }
+        self.rom_path = Some(path.to_string());
+        Ok(())
+    }
+
+    pub fn labels(&self) -> &Labels {
+        &self.labels
+    }
+
+    // Save state slots live next to the ROM, e.g. rom/TETRIS.state1
+    pub fn state_path(&self, slot: u8) -> Option<String> {
+        self.rom_path.as_ref().map(|rom| format!("{}.state{}", rom, slot))
+    }
+
+    pub fn set_screenshot_options(&mut self, palette: Palette, scale: u32) {
+        self.palette = palette;
+        self.screenshot_scale = scale;
+    }
+
+    // Saves the screen as it is now
+    pub fn save_screenshot(&self, path: &str) -> Result<()> {
+        screenshot::save_png(&self.cpu, &self.palette, self.screenshot_scale, path)
+    }
+
+    // The first free <ROM><infix>N.png next to the ROM
+    fn image_path(&self, infix: &str) -> Option<String> {
+        let rom = self.rom_path.as_ref()?;
+        (1..).map(|number| format!("{}{}{}.png", rom, infix, number)).find(|path| !std::path::Path::new(path).exists())
+    }
+
+    fn take_screenshot(&self) {
+        let Some(path) = self.image_path(".") else { return };
+        match self.save_screenshot(&path) {
+            Ok(()) => eprintln!("Saved screenshot to {}", path),
+            Err(error) => eprintln!("Could not save screenshot: {}", error),
+        }
+    }
+
+    // Records the screen of every frame the program runs, until `take_clip`
+    pub fn start_clip(&mut self) {
+        self.clip = Some(Clip::new());
+    }
+
+    pub fn take_clip(&mut self) -> Option<Clip> {
+        self.clip.take()
+    }
+
+    pub fn save_clip(&self, clip: &Clip, path: &str) -> Result<()> {
+        clip.save(&self.palette, self.screenshot_scale, path)
+    }
+
+    fn toggle_clip(&mut self) {
+        let Some(clip) = self.take_clip() else {
+            eprintln!("Recording clip");
+            self.start_clip();
+            return;
+        };
+        let Some(path) = self.image_path(".clip") else { return };
+        if clip.is_empty() {
+            eprintln!("Clip is empty, nothing saved");
+            return;
+        }
+        match self.save_clip(&clip, &path) {
+            Ok(()) => eprintln!("Saved {} frame clip to {}", clip.len(), path),
+            Err(error) => eprintln!("Could not save clip: {}", error),
+        }
+    }
+
+    fn save_slot(&self, slot: u8) {
+        let Some(path) = self.state_path(slot) else { return };
+        match snapshot::save(&self.cpu, &path) {
+            Ok(()) => eprintln!("Saved state {} to {}", slot, path),
+            Err(error) => eprintln!("Could not save state {}: {}", slot, error),
+        }
+    }
+
+    fn load_slot(&mut self, slot: u8) {
+        let Some(path) = self.state_path(slot) else { return };
+        match snapshot::load(&mut self.cpu, &path) {
+            Ok(()) => {
+                eprintln!("Loaded state {} from {}", slot, path);
+                // The frames before the load still replay, so they are kept
+                if let Some(movie) = self.recording.as_ref().filter(|_| !self.recording_ended) {
+                    eprintln!("Loading a state breaks the movie, recording stopped after {} frames", movie.frames.len());
+                    self.recording_ended = true;
+                }
+            },
+            Err(error) => eprintln!("Could not load state {}: {}", slot, error),
+        }
+    }
+
+    // Runs one 60 Hz frame, returning false once the emulator should stop
+    pub fn step_frame(&mut self) -> Result<bool> {
+        let mut rewinding = false;
+        match self.input.poll(self.cpu.keys_mut()) {
+            Command::Quit => return Ok(false),
+            Command::TogglePause => self.paused = !self.paused,
+            Command::SaveState(slot) => self.save_slot(slot),
+            Command::LoadState(slot) => self.load_slot(slot),
+            Command::Screenshot => self.take_screenshot(),
+            Command::ToggleClip => self.toggle_clip(),
+            Command::Rewind => {
+                self.rewind_frame()?;
+                rewinding = true;
+            },
+            Command::Continue => (),
+        }
+        if let Some(debugger) = &mut self.debugger {
+            if let Action::Quit = debugger.update(&mut self.cpu) {
+                return Ok(false);
+            }
+        }
+        let debugging = self.debugger.as_ref().is_some_and(Debugger::is_paused);
+        if debugging {
+            self.end_recording_for_debugger();
+        }
+        let running = !self.paused && !rewinding && !debugging;
+        if running {
+            let keys = movie::key_mask(self.cpu.keys());
+            for _ in 0..self.instructions_per_frame {
+                if let Some(debugger) = &mut self.debugger {
+                    if debugger.should_break(&self.cpu) {
+                        break;
+                    }
+                }
+                if self.clock == ClockMode::WallClock {
+                    self.update_wall_clock_timers();
+                }
+                if let Err(error) = self.cpu.cycle() {
+                    match &mut self.debugger {
+                        Some(debugger) => {
+                            debugger.report_error(&self.cpu, &error);
+                            break;
+                        },
+                        None => return Err(error),
+                    }
+                }
+                if self.report_watch_hits() {
+                    break;
+                }
+                if self.cpu.is_halted() {
+                    break;
+                }
+            }
+            if self.debugger.as_ref().is_some_and(Debugger::is_paused) {
+                self.end_recording_for_debugger();
+            } else if let Some(movie) = self.recording.as_mut().filter(|_| !self.recording_ended) {
+                movie.frames.push(keys);
+            }
+            if self.clock == ClockMode::Emulated {
+                self.cpu.tick_timers();
+            }
+            if self.rewind.is_enabled() {
+                self.rewind.push(self.cpu.save_state());
+            }
+            self.frames += 1;
+        }
+        if self.cpu.sound_active() && running {
+            self.audio.start();
+        } else {
+            self.audio.stop();
+        }
+        self.audio.set_pattern(self.cpu.audio_pattern(), self.cpu.pitch());
+        // Only frames that ran take up emulated time
+        if running {
+            self.audio.end_frame();
+        }
+        if self.cpu.take_vram_changed() {
+            self.video.draw(self.cpu.vram(), self.cpu.width(), self.cpu.height())?;
+        }
+        // Pauses are left out so the clip plays at the program's speed
+        if let Some(clip) = self.clip.as_mut().filter(|_| !self.paused && !debugging) {
+            clip.push(self.cpu.vram(), self.cpu.width(), self.cpu.height());
+        }
+        Ok(!self.cpu.is_halted())
+    }
+
+    // Runs as fast as possible until the input quits, the program exits or
+    // the frame limit is reached
+    pub fn run_unthrottled(&mut self, max_frames: Option<u64>) -> Result<()> {
+        while max_frames.is_none_or(|max| self.frames < max) && self.step_frame()? {
+            // Nothing runs while the debugger waits for a command
+            if self.debugger.as_ref().is_some_and(Debugger::is_paused) {
+                std::thread::sleep(FRAME_TIME);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn run(&mut self) -> Result<()> {
+        loop {
+            let start = Instant::now();
+            if !self.step_frame()? {
                 break;
             }
-            self.cpu.cycle(&mut self.display_driver)?;
+            if let Some(remaining) = FRAME_TIME.checked_sub(start.elapsed()) {
+                ::std::thread::sleep(remaining);
+            }
         }
         Ok(())
     }
-}
\ No newline at end of file
+}
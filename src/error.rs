@@ -0,0 +1,62 @@
+use std::fmt;
+
+#[derive(Debug)]
+pub enum Error {
+    UnknownOpcode { pc: u16, opcode: u16 },
+    StackOverflow { pc: u16 },
+    StackUnderflow { pc: u16 },
+    // I + offset points past the end of RAM
+    MemoryOutOfBounds { pc: u16, address: usize },
+    RomTooLarge { size: usize, max: usize },
+    RomNotFound { path: String },
+    InvalidSnapshot(String),
+    InvalidMovie(String),
+    // A save state or movie was made with a different ROM
+    RomMismatch { expected: u64, found: u64 },
+    // Source that doesn't assemble, at a 1-based line
+    Assembly { path: String, line: usize, message: String },
+    // Octo source that doesn't compile, at a 1-based line and column
+    Compile { path: String, line: usize, column: usize, message: String },
+    Io(std::io::Error),
+    // A frontend (window, audio device, ...) failed
+    Backend(String),
+    InvalidArgument(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::UnknownOpcode { pc, opcode } => write!(f, "unknown opcode {:04X} at PC {:#05X}", opcode, pc),
+            Error::StackOverflow { pc } => write!(f, "stack overflow at PC {:#05X}", pc),
+            Error::StackUnderflow { pc } => write!(f, "stack underflow at PC {:#05X}", pc),
+            Error::MemoryOutOfBounds { pc, address } => write!(f, "out of bounds memory access to {:#X} at PC {:#05X}", address, pc),
+            Error::RomTooLarge { size, max } => write!(f, "ROM is too large ({} bytes, at most {} fit in memory)", size, max),
+            Error::RomNotFound { path } => write!(f, "could not open ROM {}", path),
+            Error::InvalidSnapshot(message) => write!(f, "invalid save state: {}", message),
+            Error::InvalidMovie(message) => write!(f, "invalid movie: {}", message),
+            Error::RomMismatch { expected, found } => write!(f, "made for another ROM (hash {:016X}, loaded ROM is {:016X})", found, expected),
+            Error::Assembly { path, line, message } => write!(f, "{}:{}: {}", path, line, message),
+            Error::Compile { path, line, column, message } => write!(f, "{}:{}:{}: {}", path, line, column, message),
+            Error::Io(error) => write!(f, "{}", error),
+            Error::Backend(message) => write!(f, "frontend error: {}", message),
+            Error::InvalidArgument(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        Error::Io(error)
+    }
+}
@@ -0,0 +1,104 @@
+use crate::cpu::Framebuffer;
+use crate::error::Result;
+
+pub mod null;
+pub mod recording;
+pub mod scripted;
+pub mod wav;
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Continue,
+    Quit,
+    TogglePause,
+    SaveState(u8),
+    LoadState(u8),
+    // Sent on every poll while the rewind key is held
+    Rewind,
+    Screenshot,
+    ToggleClip,
+}
+
+pub trait VideoSink {
+    fn draw(&mut self, vram: &Framebuffer, width: usize, height: usize) -> Result<()>;
+}
+
+pub trait AudioSink {
+    fn start(&mut self);
+    fn stop(&mut self);
+    fn set_pattern(&mut self, pattern: Option<[u8; 16]>, pitch: u8);
+    // Called once the sound of an emulated frame is settled, for sinks that
+    // render in emulated time rather than as the host plays them
+    fn end_frame(&mut self) {}
+    // Writes out whatever the sink still buffers
+    fn finish(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+// A missing sink, e.g. when sound is muted
+impl<A: AudioSink> AudioSink for Option<A> {
+    fn start(&mut self) {
+        if let Some(audio) = self {
+            audio.start();
+        }
+    }
+
+    fn stop(&mut self) {
+        if let Some(audio) = self {
+            audio.stop();
+        }
+    }
+
+    fn set_pattern(&mut self, pattern: Option<[u8; 16]>, pitch: u8) {
+        if let Some(audio) = self {
+            audio.set_pattern(pattern, pitch);
+        }
+    }
+
+    fn end_frame(&mut self) {
+        if let Some(audio) = self {
+            audio.end_frame();
+        }
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        match self {
+            Some(audio) => audio.finish(),
+            None => Ok(()),
+        }
+    }
+}
+
+// Both sinks at once, e.g. the speakers and a WAV file
+impl<A: AudioSink, B: AudioSink> AudioSink for (A, B) {
+    fn start(&mut self) {
+        self.0.start();
+        self.1.start();
+    }
+
+    fn stop(&mut self) {
+        self.0.stop();
+        self.1.stop();
+    }
+
+    fn set_pattern(&mut self, pattern: Option<[u8; 16]>, pitch: u8) {
+        self.0.set_pattern(pattern, pitch);
+        self.1.set_pattern(pattern, pitch);
+    }
+
+    fn end_frame(&mut self) {
+        self.0.end_frame();
+        self.1.end_frame();
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.0.finish()?;
+        self.1.finish()
+    }
+}
+
+pub trait InputSource {
+    // Updates the key state and reports whether the emulator should keep going
+    fn poll(&mut self, keys: &mut [bool; 16]) -> Command;
+}
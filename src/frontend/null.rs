@@ -0,0 +1,34 @@
+use crate::cpu::Framebuffer;
+use crate::error::Result;
+
+use super::{AudioSink, Command, InputSource, VideoSink};
+
+// Frontend pieces that discard output and never press a key
+#[derive(Default)]
+pub struct NullVideo;
+
+#[derive(Default)]
+pub struct NullAudio;
+
+#[derive(Default)]
+pub struct NullInput;
+
+impl VideoSink for NullVideo {
+    fn draw(&mut self, _vram: &Framebuffer, _width: usize, _height: usize) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl AudioSink for NullAudio {
+    fn start(&mut self) {}
+
+    fn stop(&mut self) {}
+
+    fn set_pattern(&mut self, _pattern: Option<[u8; 16]>, _pitch: u8) {}
+}
+
+impl InputSource for NullInput {
+    fn poll(&mut self, _keys: &mut [bool; 16]) -> Command {
+        Command::Continue
+    }
+}
@@ -0,0 +1,40 @@
+use std::io::Write;
+
+use crate::cpu::Framebuffer;
+use crate::error::Result;
+
+use super::VideoSink;
+
+// Writes every drawn frame to `out` as a "width height" line followed by one
+// line of plane bits per row
+pub struct RecordingVideo<W: Write> {
+    out: W,
+    frames: usize,
+}
+
+impl<W: Write> RecordingVideo<W> {
+    pub fn new(out: W) -> Self {
+        Self { out, frames: 0 }
+    }
+
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+impl<W: Write> VideoSink for RecordingVideo<W> {
+    fn draw(&mut self, vram: &Framebuffer, width: usize, height: usize) -> Result<()> {
+        let mut text = format!("{} {}\n", width, height);
+        for row in vram.iter().take(height) {
+            text.extend(row.iter().take(width).map(|&pixel| char::from(b'0' + pixel)));
+            text.push('\n');
+        }
+        self.out.write_all(text.as_bytes())?;
+        self.frames += 1;
+        Ok(())
+    }
+}
@@ -0,0 +1,42 @@
+use std::collections::VecDeque;
+
+use super::{Command, InputSource};
+
+// Replays a fixed list of key states, one per poll, and quits once it runs out
+pub struct ScriptedInput {
+    steps: VecDeque<[bool; 16]>,
+}
+
+impl ScriptedInput {
+    pub fn new(steps: impl IntoIterator<Item = [bool; 16]>) -> Self {
+        Self {
+            steps: steps.into_iter().collect(),
+        }
+    }
+
+    // Holds the keys of `mask` (bit N for key N) for `polls` polls
+    pub fn hold(mut self, mask: u16, polls: usize) -> Self {
+        let mut keys = [false; 16];
+        for (i, key) in keys.iter_mut().enumerate() {
+            *key = mask & (1 << i) != 0;
+        }
+        self.steps.extend(std::iter::repeat_n(keys, polls));
+        self
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.steps.len()
+    }
+}
+
+impl InputSource for ScriptedInput {
+    fn poll(&mut self, keys: &mut [bool; 16]) -> Command {
+        match self.steps.pop_front() {
+            Some(step) => {
+                *keys = step;
+                Command::Continue
+            },
+            None => Command::Quit,
+        }
+    }
+}
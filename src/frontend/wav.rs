@@ -0,0 +1,112 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+
+use crate::buzzer::{Buzzer, Tone, SAMPLE_RATE};
+use crate::error::Result;
+
+use super::AudioSink;
+
+// Samples in one 60 Hz frame
+pub const SAMPLES_PER_FRAME: usize = SAMPLE_RATE as usize / 60;
+
+const HEADER_SIZE: u32 = 44;
+
+pub type WavFile = WavAudio<BufWriter<File>>;
+
+// Renders the sound into a 16-bit mono WAV file in emulated time: every
+// frame that ran adds exactly 1/60 s, silent unless the sound timer was
+// running or its release is still fading, so the file lines up with the
+// frame count however fast the emulator went
+pub struct WavAudio<W: Write + Seek> {
+    out: W,
+    buzzer: Buzzer,
+    samples: u32,
+    // The first write error, reported by finish since end_frame can't
+    error: Option<io::Error>,
+}
+
+impl WavAudio<BufWriter<File>> {
+    pub fn create(path: &str, tone: Tone) -> Result<Self> {
+        Ok(Self::new(BufWriter::new(File::create(path)?), tone)?)
+    }
+}
+
+impl<W: Write + Seek> WavAudio<W> {
+    // Writes a header for an empty file, filled in by finish
+    pub fn new(mut out: W, tone: Tone) -> io::Result<Self> {
+        write_header(&mut out, 0)?;
+        Ok(Self {
+            out,
+            buzzer: Buzzer::new(SAMPLE_RATE, tone),
+            samples: 0,
+            error: None,
+        })
+    }
+
+    pub fn samples(&self) -> u32 {
+        self.samples
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    fn write_frame(&mut self) -> io::Result<()> {
+        let mut frame = [0.0; SAMPLES_PER_FRAME];
+        self.buzzer.fill(&mut frame);
+        let bytes: Vec<u8> = frame.iter().flat_map(|&sample| ((sample * i16::MAX as f32) as i16).to_le_bytes()).collect();
+        self.out.write_all(&bytes)?;
+        self.samples += SAMPLES_PER_FRAME as u32;
+        Ok(())
+    }
+}
+
+impl<W: Write + Seek> AudioSink for WavAudio<W> {
+    fn start(&mut self) {
+        self.buzzer.set_gate(true);
+    }
+
+    fn stop(&mut self) {
+        self.buzzer.set_gate(false);
+    }
+
+    fn set_pattern(&mut self, pattern: Option<[u8; 16]>, pitch: u8) {
+        self.buzzer.set_pattern(pattern, pitch);
+    }
+
+    fn end_frame(&mut self) {
+        if self.error.is_none() {
+            self.error = self.write_frame().err();
+        }
+    }
+
+    // Rewrites the header with the final length; the file stays valid, and
+    // recording can go on, after each call
+    fn finish(&mut self) -> Result<()> {
+        if let Some(error) = self.error.take() {
+            return Err(error.into());
+        }
+        self.out.seek(SeekFrom::Start(0))?;
+        write_header(&mut self.out, self.samples)?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+fn write_header<W: Write>(out: &mut W, samples: u32) -> io::Result<()> {
+    let data_size = samples * 2;
+    out.write_all(b"RIFF")?;
+    out.write_all(&(HEADER_SIZE - 8 + data_size).to_le_bytes())?;
+    out.write_all(b"WAVE")?;
+    out.write_all(b"fmt ")?;
+    out.write_all(&16u32.to_le_bytes())?;
+    out.write_all(&1u16.to_le_bytes())?; // PCM
+    out.write_all(&1u16.to_le_bytes())?; // mono
+    out.write_all(&SAMPLE_RATE.to_le_bytes())?;
+    out.write_all(&(SAMPLE_RATE * 2).to_le_bytes())?; // bytes per second
+    out.write_all(&2u16.to_le_bytes())?; // bytes per sample
+    out.write_all(&16u16.to_le_bytes())?; // bits per sample
+    out.write_all(b"data")?;
+    out.write_all(&data_size.to_le_bytes())
+}
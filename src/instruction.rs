@@ -0,0 +1,354 @@
+/// A decoded CHIP-8, SCHIP or XO-CHIP instruction. Register operands are
+/// register numbers, not values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    // 00CN/00DN: scroll the display down/up N lines
+    ScrollDown(u8),
+    ScrollUp(u8),
+    // 00E0
+    Clear,
+    // 00EE
+    Return,
+    // 00FB/00FC: scroll the display right/left 4 pixels
+    ScrollRight,
+    ScrollLeft,
+    // 00FD
+    Exit,
+    // 00FE/00FF
+    Lores,
+    Hires,
+    // 1NNN
+    Jump(u16),
+    // 2NNN
+    Call(u16),
+    // 3XNN/4XNN
+    SkipIfEqual(u8, u8),
+    SkipIfNotEqual(u8, u8),
+    // 5XY0/9XY0
+    SkipIfRegistersEqual(u8, u8),
+    SkipIfRegistersNotEqual(u8, u8),
+    // 5XY2/5XY3: save/load VX..VY at I
+    SaveRange(u8, u8),
+    LoadRange(u8, u8),
+    // 6XNN/7XNN
+    Set(u8, u8),
+    Add(u8, u8),
+    // 8XY0-8XY7, 8XYE
+    Copy(u8, u8),
+    Or(u8, u8),
+    And(u8, u8),
+    Xor(u8, u8),
+    AddRegister(u8, u8),
+    Subtract(u8, u8),
+    ShiftRight(u8, u8),
+    SubtractReversed(u8, u8),
+    ShiftLeft(u8, u8),
+    // ANNN
+    SetIndex(u16),
+    // BNNN, or BXNN under the jump_uses_vx quirk
+    JumpOffset(u16),
+    // CXNN
+    Random(u8, u8),
+    // DXYN
+    Draw(u8, u8, u8),
+    // EX9E/EXA1
+    SkipIfKey(u8),
+    SkipIfNotKey(u8),
+    // F000 NNNN: the address is the word following the opcode
+    SetIndexLong(u16),
+    // FN01
+    Plane(u8),
+    // F002
+    Audio,
+    // FX07/FX0A
+    GetDelay(u8),
+    WaitKey(u8),
+    // FX15/FX18
+    SetDelay(u8),
+    SetSound(u8),
+    // FX1E
+    AddIndex(u8),
+    // FX29/FX30
+    Font(u8),
+    BigFont(u8),
+    // FX33
+    Bcd(u8),
+    // FX3A
+    Pitch(u8),
+    // FX55/FX65: store/load V0..VX at I
+    Store(u8),
+    Load(u8),
+    // FX75/FX85: save/restore V0..VX to the RPL user flags
+    SaveFlags(u8),
+    LoadFlags(u8),
+    // Anything else, usually data
+    Unknown(u16),
+}
+
+impl Instruction {
+    // `next` is the word after the opcode, only used by F000 NNNN
+    pub fn decode(opcode: u16, next: u16) -> Self {
+        use Instruction::*;
+        let x = ((opcode & 0x0F00) >> 8) as u8;
+        let y = ((opcode & 0x00F0) >> 4) as u8;
+        let n = (opcode & 0x000F) as u8;
+        let nn = (opcode & 0x00FF) as u8;
+        let nnn = opcode & 0x0FFF;
+        match (opcode & 0xF000) >> 12 {
+            0x0 => match nn {
+                0xC0..=0xCF => ScrollDown(n),
+                0xD0..=0xDF => ScrollUp(n),
+                0xE0 => Clear,
+                0xEE => Return,
+                0xFB => ScrollRight,
+                0xFC => ScrollLeft,
+                0xFD => Exit,
+                0xFE => Lores,
+                0xFF => Hires,
+                _ => Unknown(opcode),
+            },
+            0x1 => Jump(nnn),
+            0x2 => Call(nnn),
+            0x3 => SkipIfEqual(x, nn),
+            0x4 => SkipIfNotEqual(x, nn),
+            0x5 => match n {
+                0x0 => SkipIfRegistersEqual(x, y),
+                0x2 => SaveRange(x, y),
+                0x3 => LoadRange(x, y),
+                _ => Unknown(opcode),
+            },
+            0x6 => Set(x, nn),
+            0x7 => Add(x, nn),
+            0x8 => match n {
+                0x0 => Copy(x, y),
+                0x1 => Or(x, y),
+                0x2 => And(x, y),
+                0x3 => Xor(x, y),
+                0x4 => AddRegister(x, y),
+                0x5 => Subtract(x, y),
+                0x6 => ShiftRight(x, y),
+                0x7 => SubtractReversed(x, y),
+                0xE => ShiftLeft(x, y),
+                _ => Unknown(opcode),
+            },
+            // The low nibble is ignored, as on the original interpreter
+            0x9 => SkipIfRegistersNotEqual(x, y),
+            0xA => SetIndex(nnn),
+            0xB => JumpOffset(nnn),
+            0xC => Random(x, nn),
+            0xD => Draw(x, y, n),
+            0xE => match nn {
+                0x9E => SkipIfKey(x),
+                0xA1 => SkipIfNotKey(x),
+                _ => Unknown(opcode),
+            },
+            _ => match nn {
+                0x00 if x == 0 => SetIndexLong(next),
+                0x01 => Plane(x),
+                0x02 if x == 0 => Audio,
+                0x07 => GetDelay(x),
+                0x0A => WaitKey(x),
+                0x15 => SetDelay(x),
+                0x18 => SetSound(x),
+                0x1E => AddIndex(x),
+                0x29 => Font(x),
+                0x30 => BigFont(x),
+                0x33 => Bcd(x),
+                0x3A => Pitch(x),
+                0x55 => Store(x),
+                0x65 => Load(x),
+                0x75 => SaveFlags(x),
+                0x85 => LoadFlags(x),
+                _ => Unknown(opcode),
+            },
+        }
+    }
+
+    // Inverse of `decode`: the opcode, followed by the address of F000 NNNN
+    pub fn encode(&self) -> Vec<u16> {
+        use Instruction::*;
+        let xy = |prefix: u16, x: u8, y: u8, n: u16| prefix | (x as u16) << 8 | (y as u16) << 4 | n;
+        let xnn = |prefix: u16, x: u8, nn: u8| prefix | (x as u16) << 8 | nn as u16;
+        let fx = |x: u8, nn: u16| 0xF000 | (x as u16) << 8 | nn;
+        let opcode = match *self {
+            ScrollDown(n) => 0x00C0 | n as u16,
+            ScrollUp(n) => 0x00D0 | n as u16,
+            Clear => 0x00E0,
+            Return => 0x00EE,
+            ScrollRight => 0x00FB,
+            ScrollLeft => 0x00FC,
+            Exit => 0x00FD,
+            Lores => 0x00FE,
+            Hires => 0x00FF,
+            Jump(address) => 0x1000 | address,
+            Call(address) => 0x2000 | address,
+            SkipIfEqual(x, nn) => xnn(0x3000, x, nn),
+            SkipIfNotEqual(x, nn) => xnn(0x4000, x, nn),
+            SkipIfRegistersEqual(x, y) => xy(0x5000, x, y, 0x0),
+            SaveRange(x, y) => xy(0x5000, x, y, 0x2),
+            LoadRange(x, y) => xy(0x5000, x, y, 0x3),
+            Set(x, nn) => xnn(0x6000, x, nn),
+            Add(x, nn) => xnn(0x7000, x, nn),
+            Copy(x, y) => xy(0x8000, x, y, 0x0),
+            Or(x, y) => xy(0x8000, x, y, 0x1),
+            And(x, y) => xy(0x8000, x, y, 0x2),
+            Xor(x, y) => xy(0x8000, x, y, 0x3),
+            AddRegister(x, y) => xy(0x8000, x, y, 0x4),
+            Subtract(x, y) => xy(0x8000, x, y, 0x5),
+            ShiftRight(x, y) => xy(0x8000, x, y, 0x6),
+            SubtractReversed(x, y) => xy(0x8000, x, y, 0x7),
+            ShiftLeft(x, y) => xy(0x8000, x, y, 0xE),
+            SkipIfRegistersNotEqual(x, y) => xy(0x9000, x, y, 0x0),
+            SetIndex(address) => 0xA000 | address,
+            JumpOffset(address) => 0xB000 | address,
+            Random(x, nn) => xnn(0xC000, x, nn),
+            Draw(x, y, n) => xy(0xD000, x, y, n as u16),
+            SkipIfKey(x) => xnn(0xE000, x, 0x9E),
+            SkipIfNotKey(x) => xnn(0xE000, x, 0xA1),
+            SetIndexLong(address) => return vec![0xF000, address],
+            Plane(n) => fx(n, 0x01),
+            Audio => 0xF002,
+            GetDelay(x) => fx(x, 0x07),
+            WaitKey(x) => fx(x, 0x0A),
+            SetDelay(x) => fx(x, 0x15),
+            SetSound(x) => fx(x, 0x18),
+            AddIndex(x) => fx(x, 0x1E),
+            Font(x) => fx(x, 0x29),
+            BigFont(x) => fx(x, 0x30),
+            Bcd(x) => fx(x, 0x33),
+            Pitch(x) => fx(x, 0x3A),
+            Store(x) => fx(x, 0x55),
+            Load(x) => fx(x, 0x65),
+            SaveFlags(x) => fx(x, 0x75),
+            LoadFlags(x) => fx(x, 0x85),
+            Unknown(word) => word,
+        };
+        vec![opcode]
+    }
+
+    // Size in bytes
+    pub fn size(&self) -> usize {
+        match self {
+            Instruction::SetIndexLong(_) => 4,
+            _ => 2,
+        }
+    }
+
+    // Instructions after which execution doesn't fall through to the next one
+    pub fn ends_block(&self) -> bool {
+        matches!(self, Instruction::Jump(_) | Instruction::JumpOffset(_) | Instruction::Return | Instruction::Exit)
+    }
+
+    // Instructions that may skip the one that follows
+    pub fn is_skip(&self) -> bool {
+        use Instruction::*;
+        matches!(
+            self,
+            SkipIfEqual(..) | SkipIfNotEqual(..) | SkipIfRegistersEqual(..) | SkipIfRegistersNotEqual(..) | SkipIfKey(_) | SkipIfNotKey(_)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Instruction::{self, *};
+    use crate::disasm::{self, Syntax};
+
+    // Words, instruction, Cowgod and Octo disassembly
+    const TABLE: &[(&[u16], Instruction, &str, &str)] = &[
+        (&[0x00C5], ScrollDown(5), "SCD 5", "scroll-down 5"),
+        (&[0x00DA], ScrollUp(10), "SCU 10", "scroll-up 10"),
+        (&[0x00E0], Clear, "CLS", "clear"),
+        (&[0x00EE], Return, "RET", "return"),
+        (&[0x00FB], ScrollRight, "SCR", "scroll-right"),
+        (&[0x00FC], ScrollLeft, "SCL", "scroll-left"),
+        (&[0x00FD], Exit, "EXIT", "exit"),
+        (&[0x00FE], Lores, "LOW", "lores"),
+        (&[0x00FF], Hires, "HIGH", "hires"),
+        (&[0x1234], Jump(0x234), "JP 0x234", "jump 0x234"),
+        (&[0x2ABC], Call(0xABC), "CALL 0xABC", ":call 0xABC"),
+        (&[0x3A12], SkipIfEqual(0xA, 0x12), "SE VA, 0x12", "if va != 0x12 then"),
+        (&[0x4B34], SkipIfNotEqual(0xB, 0x34), "SNE VB, 0x34", "if vb == 0x34 then"),
+        (&[0x5120], SkipIfRegistersEqual(1, 2), "SE V1, V2", "if v1 != v2 then"),
+        (&[0x5342], SaveRange(3, 4), "SAVE V3 - V4", "save v3 - v4"),
+        (&[0x5533], LoadRange(5, 3), "LOAD V5 - V3", "load v5 - v3"),
+        (&[0x6CFF], Set(0xC, 0xFF), "LD VC, 0xFF", "vc := 0xFF"),
+        (&[0x7D01], Add(0xD, 0x01), "ADD VD, 0x01", "vd += 0x01"),
+        (&[0x8120], Copy(1, 2), "LD V1, V2", "v1 := v2"),
+        (&[0x8121], Or(1, 2), "OR V1, V2", "v1 |= v2"),
+        (&[0x8122], And(1, 2), "AND V1, V2", "v1 &= v2"),
+        (&[0x8123], Xor(1, 2), "XOR V1, V2", "v1 ^= v2"),
+        (&[0x8124], AddRegister(1, 2), "ADD V1, V2", "v1 += v2"),
+        (&[0x8125], Subtract(1, 2), "SUB V1, V2", "v1 -= v2"),
+        (&[0x8126], ShiftRight(1, 2), "SHR V1, V2", "v1 >>= v2"),
+        (&[0x8127], SubtractReversed(1, 2), "SUBN V1, V2", "v1 =- v2"),
+        (&[0x812E], ShiftLeft(1, 2), "SHL V1, V2", "v1 <<= v2"),
+        (&[0x9EF0], SkipIfRegistersNotEqual(0xE, 0xF), "SNE VE, VF", "if ve == vf then"),
+        (&[0xA2F0], SetIndex(0x2F0), "LD I, 0x2F0", "i := 0x2F0"),
+        (&[0xB300], JumpOffset(0x300), "JP V0, 0x300", "jump0 0x300"),
+        (&[0xC70F], Random(7, 0x0F), "RND V7, 0x0F", "v7 := random 0x0F"),
+        (&[0xD125], Draw(1, 2, 5), "DRW V1, V2, 5", "sprite v1 v2 5"),
+        (&[0xD340], Draw(3, 4, 0), "DRW V3, V4, 0", "sprite v3 v4 0"),
+        (&[0xE59E], SkipIfKey(5), "SKP V5", "if v5 -key then"),
+        (&[0xE6A1], SkipIfNotKey(6), "SKNP V6", "if v6 key then"),
+        (&[0xF000, 0xBEEF], SetIndexLong(0xBEEF), "LD I, LONG 0xBEEF", "i := long 0xBEEF"),
+        (&[0xF301], Plane(3), "PLANE 3", "plane 3"),
+        (&[0xF002], Audio, "AUDIO", "audio"),
+        (&[0xF107], GetDelay(1), "LD V1, DT", "v1 := delay"),
+        (&[0xF20A], WaitKey(2), "LD V2, K", "v2 := key"),
+        (&[0xF315], SetDelay(3), "LD DT, V3", "delay := v3"),
+        (&[0xF418], SetSound(4), "LD ST, V4", "buzzer := v4"),
+        (&[0xF51E], AddIndex(5), "ADD I, V5", "i += v5"),
+        (&[0xF629], Font(6), "LD F, V6", "i := hex v6"),
+        (&[0xF730], BigFont(7), "LD HF, V7", "i := bighex v7"),
+        (&[0xF833], Bcd(8), "LD B, V8", "bcd v8"),
+        (&[0xF93A], Pitch(9), "PITCH V9", "pitch := v9"),
+        (&[0xFA55], Store(0xA), "LD [I], VA", "save va"),
+        (&[0xFB65], Load(0xB), "LD VB, [I]", "load vb"),
+        (&[0xF775], SaveFlags(7), "LD R, V7", "saveflags v7"),
+        (&[0xF785], LoadFlags(7), "LD V7, R", "loadflags v7"),
+    ];
+
+    #[test]
+    fn decodes_and_encodes_every_instruction() {
+        for &(words, instruction, cowgod, octo) in TABLE {
+            let next = words.get(1).copied().unwrap_or(0);
+            assert_eq!(Instruction::decode(words[0], next), instruction, "{:04X}", words[0]);
+            assert_eq!(instruction.encode(), words, "{:?}", instruction);
+            assert_eq!(instruction.size(), words.len() * 2, "{:?}", instruction);
+            assert_eq!(disasm::render(instruction, Syntax::Cowgod, &|address| format!("0x{:03X}", address)), cowgod);
+            assert_eq!(disasm::render(instruction, Syntax::Octo, &|address| format!("0x{:03X}", address)), octo);
+        }
+    }
+
+    #[test]
+    fn keeps_unknown_opcodes_as_data() {
+        for opcode in [0x0000, 0x0123, 0x00E1, 0x5121, 0x8128, 0xE100, 0xF0FF, 0xF100, 0xF102] {
+            let instruction = Instruction::decode(opcode, 0x1234);
+            assert_eq!(instruction, Unknown(opcode), "{:04X}", opcode);
+            assert_eq!(instruction.encode(), [opcode]);
+            assert_eq!(instruction.size(), 2);
+            assert_eq!(disasm::mnemonic(opcode), format!("DW 0x{:04X}", opcode));
+        }
+    }
+
+    #[test]
+    fn ignores_the_low_nibble_of_9xy0() {
+        assert_eq!(Instruction::decode(0x9125, 0), SkipIfRegistersNotEqual(1, 2));
+    }
+
+    #[test]
+    fn disassembles_from_memory() {
+        let mut ram = vec![0; 0x1000];
+        ram[0x200..0x206].copy_from_slice(&[0xF0, 0x00, 0x12, 0x34, 0x00, 0xE0]);
+        assert_eq!(disasm::disassemble(&ram, 0x200), ("LD I, LONG 0x1234".to_string(), 4));
+        assert_eq!(disasm::disassemble(&ram, 0x204), ("CLS".to_string(), 2));
+        // The last word wraps around to the start of memory
+        ram[0xFFF] = 0xA0;
+        ram[0] = 0x12;
+        assert_eq!(disasm::disassemble(&ram, 0xFFF), ("LD I, 0x012".to_string(), 2));
+        let labels = [(0x234, "main".to_string())].into_iter().collect();
+        ram[0x206..0x208].copy_from_slice(&[0x22, 0x34]);
+        assert_eq!(disasm::disassemble_labelled(&ram, 0x206, &labels).0, "CALL main");
+    }
+}
@@ -0,0 +1,22 @@
+pub mod asm;
+pub mod buzzer;
+pub mod clip;
+pub mod cli;
+pub mod cpu;
+pub mod debugger;
+pub mod disasm;
+pub mod emulator;
+pub mod error;
+pub mod frontend;
+pub mod instruction;
+pub mod movie;
+pub mod octo;
+pub mod palette;
+pub mod quirks;
+pub mod rewind;
+pub mod rng;
+pub mod screenshot;
+pub mod snapshot;
+pub mod trace;
+pub mod watch;
+
@@ -1,17 +1,180 @@
-pub mod cpu;
-pub mod drivers;
-pub mod emulator;
+// The SDL frontend lives in this binary only, so the library and the text
+// tools build and link without SDL
+#[cfg(feature = "sdl")]
+mod drivers;
 
-fn main() -> Result<(), String> {
-    let mut emulator = emulator::Emulator::new()?;
+use chip8_emulator::{
+    cli::{self, Options, Parsed},
+    cpu::CPU,
+    debugger::Debugger,
+    emulator::{ClockMode, Emulator},
+    error::{Error, Result},
+    frontend::{null::{NullInput, NullVideo}, wav::WavAudio, AudioSink, InputSource, VideoSink},
+    movie::{Movie, MoviePlayer},
+    trace::Tracer,
+};
 
-    emulator.load_program("rom/TETRIS")?;
+fn main() {
+    if let Err(error) = run() {
+        eprintln!("error: {}", error);
+        if let Error::InvalidArgument(_) = error {
+            eprintln!("Run with --help for usage");
+        }
+        std::process::exit(1);
+    }
+}
+
+fn run() -> Result<()> {
+    let options = match cli::parse(std::env::args().skip(1))? {
+        Parsed::Run(options) => *options,
+        Parsed::Help => {
+            print!("{}", cli::USAGE);
+            return Ok(());
+        },
+    };
+    let movie = options.play.as_ref().map(Movie::load).transpose()?;
+    // A movie only replays faithfully on the machine it was recorded on
+    let cpu = match &movie {
+        Some(movie) => CPU::with_seed(movie.quirks, movie.seed),
+        None => match options.seed {
+            Some(seed) => CPU::with_seed(options.variant.quirks(), seed),
+            None => CPU::new(options.variant.quirks()),
+        },
+    };
+
+    if options.headless {
+        let wav = options.wav.as_deref().map(|path| WavAudio::create(path, options.tone)).transpose()?;
+        let emulator = match movie {
+            Some(movie) => {
+                let mut emulator = Emulator::new(cpu, NullVideo, wav, MoviePlayer::new(movie.clone(), NullInput));
+                prepare(&mut emulator, &options, Some(&movie))?;
+                finish(emulator, &options)?
+            },
+            None => {
+                let mut emulator = Emulator::new(cpu, NullVideo, wav, NullInput);
+                prepare(&mut emulator, &options, None)?;
+                finish(emulator, &options)?
+            },
+        };
+        println!("frames: {}", emulator.frames);
+        println!("framebuffer: {:016X}", emulator.framebuffer_hash);
+        return Ok(());
+    }
+    windowed(cpu, &options, movie)
+}
 
-    emulator.run()?;
+#[cfg(feature = "sdl")]
+fn windowed(cpu: CPU, options: &Options, movie: Option<Movie>) -> Result<()> {
+    let settings = drivers::Settings {
+        scale: options.scale,
+        palette: options.palette,
+        mute: options.mute,
+        tone: options.tone,
+        wav: options.wav.clone(),
+    };
+    match movie {
+        Some(movie) => {
+            let mut emulator = drivers::emulator_with_input(cpu, &settings, |keyboard| MoviePlayer::new(movie.clone(), keyboard))?;
+            prepare(&mut emulator, options, Some(&movie))?;
+            finish(emulator, options)?;
+        },
+        None => {
+            let mut emulator = drivers::emulator(cpu, &settings)?;
+            prepare(&mut emulator, options, None)?;
+            finish(emulator, options)?;
+        },
+    }
     Ok(())
 }
 
+#[cfg(not(feature = "sdl"))]
+fn windowed(_cpu: CPU, _options: &Options, _movie: Option<Movie>) -> Result<()> {
+    Err(Error::InvalidArgument("built without SDL support, only --headless is available".to_string()))
+}
+
+fn prepare<V: VideoSink, A: AudioSink, I: InputSource>(
+    emulator: &mut Emulator<V, A, I>,
+    options: &Options,
+    movie: Option<&Movie>,
+) -> Result<()> {
+    emulator.set_instructions_per_frame(options.instructions_per_frame);
+    emulator.set_paused(options.paused && !options.headless);
+    // Headless runs aren't paced, so host time means nothing to them
+    emulator.set_clock_mode(if options.headless { ClockMode::Emulated } else { options.clock });
+    emulator.set_rewind_seconds(options.rewind_seconds);
+    emulator.set_screenshot_options(options.palette, options.screenshot_scale);
+
+    emulator.load_program(&options.rom)?;
 
+    if let Some(movie) = movie {
+        if movie.rom_hash != emulator.cpu().rom_hash() {
+            return Err(Error::RomMismatch { expected: emulator.cpu().rom_hash(), found: movie.rom_hash });
+        }
+        emulator.set_instructions_per_frame(movie.instructions_per_frame);
+        emulator.set_clock_mode(ClockMode::Emulated);
+        // Rewinding would desynchronize the recorded keys
+        emulator.set_rewind_seconds(0);
+    }
+    if options.record.is_some() {
+        emulator.start_recording();
+    }
+    if options.clip.is_some() {
+        emulator.start_clip();
+    }
+    if let Some(path) = &options.trace {
+        let tracer = Tracer::create(path, options.trace_filter.clone())?;
+        emulator.cpu_mut().set_tracer(Some(tracer));
+    }
+    for &watchpoint in &options.watchpoints {
+        emulator.cpu_mut().watchpoints_mut().add(watchpoint);
+    }
+    if options.debug {
+        let mut debugger = Debugger::with_terminal();
+        debugger.set_labels(emulator.labels().clone());
+        emulator.set_debugger(debugger);
+    }
+    Ok(())
+}
+
+struct Summary {
+    frames: u64,
+    framebuffer_hash: u64,
+}
+
+fn finish<V: VideoSink, A: AudioSink, I: InputSource>(
+    mut emulator: Emulator<V, A, I>,
+    options: &Options,
+) -> Result<Summary> {
+    let result = if options.headless {
+        emulator.run_unthrottled(options.frames)
+    } else {
+        emulator.run()
+    };
+    // Keep whatever was recorded even if the program crashed
+    if let (Some(path), Some(movie)) = (&options.record, emulator.take_recording()) {
+        movie.save(path)?;
+        eprintln!("Recorded {} frames to {}", movie.frames.len(), path);
+    }
+    if let (Some(path), Some(clip)) = (&options.clip, emulator.take_clip()) {
+        emulator.save_clip(&clip, path)?;
+        eprintln!("Recorded {} frame clip to {}", clip.len(), path);
+    }
+    if let Some(path) = &options.wav {
+        emulator.finish_audio()?;
+        eprintln!("Recorded {:.2} s of sound to {}", emulator.frames() as f64 / 60.0, path);
+    }
+    if let Some(path) = &options.screenshot {
+        emulator.save_screenshot(path)?;
+    }
+    if let Some(mut tracer) = emulator.cpu_mut().take_tracer() {
+        tracer.flush()?;
+    }
+    result?;
+    Ok(Summary {
+        frames: emulator.frames(),
+        framebuffer_hash: emulator.cpu().framebuffer_hash(),
+    })
+}
 
 /*
     ALU:
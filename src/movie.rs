@@ -0,0 +1,116 @@
+use std::path::Path;
+
+use crate::error::{Error, Result};
+use crate::frontend::{Command, InputSource};
+use crate::quirks::Quirks;
+use crate::snapshot::{Reader, Writer};
+
+const MAGIC: &[u8; 4] = b"C8MV";
+pub const VERSION: u16 = 2;
+
+// Everything needed to replay a session from power-on: the machine setup and
+// the keypad state of every emulated frame. Replays need the emulated clock.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    pub rom_hash: u64,
+    pub quirks: Quirks,
+    pub seed: u64,
+    pub instructions_per_frame: u32,
+    // Bit N is set while key N is held
+    pub frames: Vec<u16>,
+}
+
+pub fn key_mask(keys: &[bool; 16]) -> u16 {
+    keys.iter().enumerate().fold(0, |mask, (i, &pressed)| mask | ((pressed as u16) << i))
+}
+
+impl Movie {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut writer = Writer::new();
+        writer.bytes(MAGIC);
+        writer.u16(VERSION);
+        writer.u64(self.rom_hash);
+        writer.u8(self.quirks.to_bits());
+        writer.u64(self.seed);
+        writer.u32(self.instructions_per_frame);
+        writer.u32(self.frames.len() as u32);
+        for &frame in &self.frames {
+            writer.u16(frame);
+        }
+        writer.finish()
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(bytes);
+        if reader.bytes(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+            return Err(Error::InvalidMovie("not a movie file".to_string()));
+        }
+        let version = reader.u16()?;
+        if version != VERSION {
+            return Err(Error::InvalidMovie(format!("unsupported version {}", version)));
+        }
+        let rom_hash = reader.u64()?;
+        let quirks = Quirks::from_bits(reader.u8()?);
+        let seed = reader.u64()?;
+        let instructions_per_frame = reader.u32()?;
+        let count = reader.u32()? as usize;
+        let frames = (0..count).map(|_| reader.u16()).collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            rom_hash,
+            quirks,
+            seed,
+            instructions_per_frame,
+            frames,
+        })
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        std::fs::write(path, self.encode())?;
+        Ok(())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        Self::decode(&std::fs::read(path)?)
+    }
+}
+
+// Feeds the recorded keys back frame by frame and quits at the end of the
+// movie. The wrapped source is only listened to for Quit, so a window stays
+// responsive while its keys are ignored.
+pub struct MoviePlayer<I: InputSource> {
+    frames: std::vec::IntoIter<u16>,
+    inner: I,
+    played: usize,
+}
+
+impl<I: InputSource> MoviePlayer<I> {
+    pub fn new(movie: Movie, inner: I) -> Self {
+        Self {
+            frames: movie.frames.into_iter(),
+            inner,
+            played: 0,
+        }
+    }
+
+    pub fn played(&self) -> usize {
+        self.played
+    }
+}
+
+impl<I: InputSource> InputSource for MoviePlayer<I> {
+    fn poll(&mut self, keys: &mut [bool; 16]) -> Command {
+        if self.inner.poll(&mut [false; 16]) == Command::Quit {
+            return Command::Quit;
+        }
+        match self.frames.next() {
+            Some(mask) => {
+                for (i, key) in keys.iter_mut().enumerate() {
+                    *key = mask & (1 << i) != 0;
+                }
+                self.played += 1;
+                Command::Continue
+            },
+            None => Command::Quit,
+        }
+    }
+}
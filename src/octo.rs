@@ -0,0 +1,943 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::cpu::{RAM_SIZE, START_RAM_ADDRESS};
+use crate::disasm::Labels;
+use crate::error::{Error, Result};
+use crate::instruction::Instruction;
+
+// Macros expanding into themselves are caught at this depth
+const MAX_MACRO_DEPTH: usize = 64;
+
+const START: u16 = START_RAM_ADDRESS as u16;
+
+// A compiled program, with the address of every label for the debugger
+#[derive(Debug, Clone, Default)]
+pub struct Program {
+    pub rom: Vec<u8>,
+    pub labels: Labels,
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    line: usize,
+    column: usize,
+    // Number of macro expansions that produced this token
+    depth: usize,
+}
+
+struct Macro {
+    arguments: Vec<String>,
+    body: Vec<Token>,
+}
+
+// Where a label used before its definition has to be patched in
+#[derive(Clone, Copy)]
+enum Patch {
+    // The low 12 bits of the instruction at the address
+    Address,
+    // The word following F000
+    Long,
+    // The two 6XNN of :unpack, the nibble of the first one being kept
+    Unpack,
+    // The two 6XNN of :unpack long
+    UnpackLong,
+}
+
+struct Fixup {
+    address: u16,
+    patch: Patch,
+    token: Token,
+}
+
+struct Loop {
+    start: u16,
+    token: Token,
+    // Jumps out of the loop, left by `while`
+    exits: Vec<u16>,
+}
+
+// Pending `if ... begin`, holding the jump to patch at `else` or `end`
+struct Branch {
+    jump: u16,
+    token: Token,
+}
+
+// Compiles Octo source into a ROM loaded at 0x200. Execution starts at the
+// `main` label, through a jump unless main is the first thing in the program.
+pub fn compile(source: &str, path: &str) -> Result<Program> {
+    let mut compiler = Compiler::new(source, path);
+    compiler.run()?;
+    Ok(Program { rom: compiler.rom, labels: compiler.labels })
+}
+
+pub fn compile_file(path: &str) -> Result<Program> {
+    let source = std::fs::read_to_string(path).map_err(|_| Error::RomNotFound { path: path.to_string() })?;
+    compile(&source, path)
+}
+
+// Whitespace separated tokens, with `#` comments running to the end of the line
+fn tokenize(source: &str) -> VecDeque<Token> {
+    let mut tokens = VecDeque::new();
+    for (index, line) in source.lines().enumerate() {
+        let line_text = line.split('#').next().unwrap_or("");
+        let mut start = None;
+        for (position, character) in line_text.char_indices().chain([(line_text.len(), ' ')]) {
+            match (character.is_whitespace(), start) {
+                (false, None) => start = Some(position),
+                (true, Some(from)) => {
+                    tokens.push_back(Token {
+                        text: line_text[from..position].to_string(),
+                        line: index + 1,
+                        column: line_text[..from].chars().count() + 1,
+                        depth: 0,
+                    });
+                    start = None;
+                },
+                _ => (),
+            }
+        }
+    }
+    tokens
+}
+
+fn number(text: &str) -> Option<f64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let lower = digits.to_ascii_lowercase();
+    let value = if let Some(hex) = lower.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = lower.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()?
+    } else if lower.starts_with(|character: char| character.is_ascii_digit()) {
+        lower.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -value } else { value } as f64)
+}
+
+fn parse_register(text: &str) -> Option<u8> {
+    let digit = text.strip_prefix('v').or_else(|| text.strip_prefix('V'))?;
+    if digit.len() != 1 {
+        return None;
+    }
+    u8::from_str_radix(digit, 16).ok()
+}
+
+fn is_binary_operator(text: &str) -> bool {
+    matches!(
+        text,
+        "+" | "-" | "*" | "/" | "%" | "&" | "|" | "^" | "<<" | ">>" | "pow" | "min" | "max" | "<" | "<=" | "==" | "!=" | ">=" | ">"
+    )
+}
+
+struct Compiler<'a> {
+    path: &'a str,
+    tokens: VecDeque<Token>,
+    // The token being compiled, for error positions
+    current: Token,
+    rom: Vec<u8>,
+    here: u16,
+    labels: Labels,
+    addresses: HashMap<String, u16>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    loops: Vec<Loop>,
+    branches: Vec<Branch>,
+}
+
+impl<'a> Compiler<'a> {
+    fn new(source: &str, path: &'a str) -> Self {
+        Self {
+            path,
+            tokens: tokenize(source),
+            current: Token { text: String::new(), line: 1, column: 1, depth: 0 },
+            rom: Vec::new(),
+            here: START,
+            labels: Labels::new(),
+            addresses: HashMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            fixups: Vec::new(),
+            loops: Vec::new(),
+            branches: Vec::new(),
+        }
+    }
+
+    fn error_at(&self, token: &Token, message: impl Into<String>) -> Error {
+        Error::Compile { path: self.path.to_string(), line: token.line, column: token.column, message: message.into() }
+    }
+
+    fn error(&self, message: impl Into<String>) -> Error {
+        self.error_at(&self.current, message)
+    }
+
+    fn run(&mut self) -> Result<()> {
+        // Room for the jump to main, dropped if main turns out to be right here
+        self.emit(Instruction::Jump(0))?;
+        while let Some(token) = self.tokens.pop_front() {
+            self.current = token.clone();
+            self.statement(token)?;
+        }
+
+        if let Some(open) = self.loops.first() {
+            return Err(self.error_at(&open.token, "loop without a matching again"));
+        }
+        if let Some(open) = self.branches.first() {
+            return Err(self.error_at(&open.token, "begin without a matching end"));
+        }
+        let Some(&main) = self.addresses.get("main") else {
+            return Err(Error::Compile { path: self.path.to_string(), line: 1, column: 1, message: "program has no main label".to_string() });
+        };
+        if main != START {
+            self.write_word(START, Instruction::Jump(main).encode()[0]);
+        }
+        for fixup in std::mem::take(&mut self.fixups) {
+            let Some(&target) = self.addresses.get(&fixup.token.text) else {
+                return Err(self.error_at(&fixup.token, format!("undefined name {}", fixup.token.text)));
+            };
+            self.patch(fixup.address, fixup.patch, target, &fixup.token)?;
+        }
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<Token> {
+        match self.tokens.pop_front() {
+            Some(token) => {
+                self.current = token.clone();
+                Ok(token)
+            },
+            None => Err(self.error(format!("unexpected end of file after {}", self.current.text))),
+        }
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.front().map(|token| token.text.as_str())
+    }
+
+    fn expect(&mut self, text: &str) -> Result<()> {
+        let token = self.next()?;
+        if token.text != text {
+            return Err(self.error(format!("expected {}, found {}", text, token.text)));
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, token: Token) -> Result<()> {
+        use Instruction::*;
+        match token.text.as_str() {
+            ":" => {
+                let name = self.name()?;
+                // Nothing but the reserved jump was emitted, so main can start at 0x200
+                if name == "main" && self.here == START + 2 && self.rom.len() == 2 && self.addresses.is_empty() {
+                    self.rom.clear();
+                    self.here = START;
+                }
+                self.define_label(name, self.here)?;
+            },
+            ":next" => {
+                let name = self.name()?;
+                self.define_label(name, self.here + 1)?;
+            },
+            ":alias" => {
+                let name = self.name()?;
+                let register = self.register()?;
+                self.aliases.insert(name, register);
+            },
+            ":const" => {
+                let name = self.name()?;
+                let value = self.constant()?;
+                self.define_constant(name, value)?;
+            },
+            ":calc" => {
+                let name = self.name()?;
+                let value = self.calc()?;
+                self.define_constant(name, value)?;
+            },
+            ":assert" => {
+                let message = self.next()?;
+                let position = self.current.clone();
+                if self.calc()? == 0.0 {
+                    return Err(self.error_at(&position, format!("assertion failed: {}", message.text)));
+                }
+            },
+            ":macro" => self.define_macro()?,
+            ":org" => {
+                let address = self.constant()?;
+                if !(START as f64..RAM_SIZE as f64).contains(&address) {
+                    return Err(self.error(format!("cannot place code at {}", address)));
+                }
+                self.here = address as u16;
+            },
+            ":byte" => {
+                let value = if self.peek() == Some("{") { self.calc()? } else { self.constant()? };
+                // Errors point at :byte rather than the end of the expression
+                self.current = token;
+                let byte = self.byte_value(value)?;
+                self.write_bytes(&[byte])?;
+            },
+            ":unpack" => {
+                let (long, nibble) = if self.peek() == Some("long") {
+                    self.next()?;
+                    (true, 0)
+                } else {
+                    (false, self.nibble()?)
+                };
+                let target = self.next()?;
+                let address = self.here;
+                self.emit(Set(0, nibble << 4))?;
+                self.emit(Set(1, 0))?;
+                let patch = if long { Patch::UnpackLong } else { Patch::Unpack };
+                self.reference(address, patch, target)?;
+            },
+            ":call" => {
+                let target = self.next()?;
+                self.call(target)?;
+            },
+            // Only meaningful in the Octo IDE
+            ":breakpoint" => {
+                self.next()?;
+            },
+            ":monitor" => {
+                self.next()?;
+                self.next()?;
+            },
+            ";" | "return" => self.emit(Return)?,
+            "clear" => self.emit(Clear)?,
+            "exit" => self.emit(Exit)?,
+            "hires" => self.emit(Hires)?,
+            "lores" => self.emit(Lores)?,
+            "scroll-left" => self.emit(ScrollLeft)?,
+            "scroll-right" => self.emit(ScrollRight)?,
+            "scroll-down" => {
+                let lines = self.nibble()?;
+                self.emit(ScrollDown(lines))?;
+            },
+            "scroll-up" => {
+                let lines = self.nibble()?;
+                self.emit(ScrollUp(lines))?;
+            },
+            "plane" => {
+                let mask = self.nibble()?;
+                self.emit(Plane(mask))?;
+            },
+            "audio" => self.emit(Audio)?,
+            "bcd" => {
+                let x = self.register()?;
+                self.emit(Bcd(x))?;
+            },
+            "save" | "load" => {
+                let x = self.register()?;
+                let instruction = if self.peek() == Some("-") {
+                    self.next()?;
+                    let y = self.register()?;
+                    if token.text == "save" { SaveRange(x, y) } else { LoadRange(x, y) }
+                } else if token.text == "save" {
+                    Store(x)
+                } else {
+                    Load(x)
+                };
+                self.emit(instruction)?;
+            },
+            "saveflags" => {
+                let x = self.register()?;
+                self.emit(SaveFlags(x))?;
+            },
+            "loadflags" => {
+                let x = self.register()?;
+                self.emit(LoadFlags(x))?;
+            },
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let height = self.nibble()?;
+                self.emit(Draw(x, y, height))?;
+            },
+            "jump" | "jump0" | "native" => {
+                let target = self.next()?;
+                let address = self.here;
+                self.emit(match token.text.as_str() {
+                    "jump" => Jump(0),
+                    "jump0" => JumpOffset(0),
+                    _ => Unknown(0),
+                })?;
+                self.reference(address, Patch::Address, target)?;
+            },
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                self.emit(match token.text.as_str() {
+                    "delay" => SetDelay(x),
+                    "buzzer" => SetSound(x),
+                    _ => Pitch(x),
+                })?;
+            },
+            "i" | "I" => self.index()?,
+            "loop" => self.loops.push(Loop { start: self.here, token, exits: Vec::new() }),
+            "while" => {
+                if self.loops.is_empty() {
+                    return Err(self.error("while outside of a loop"));
+                }
+                // Skip the jump out while the condition holds
+                self.condition(true)?;
+                let jump = self.here;
+                self.emit(Jump(0))?;
+                self.loops.last_mut().unwrap().exits.push(jump);
+            },
+            "again" => {
+                let Some(open) = self.loops.pop() else {
+                    return Err(self.error("again without a matching loop"));
+                };
+                self.emit(Jump(open.start))?;
+                for exit in open.exits {
+                    self.patch(exit, Patch::Address, self.here, &token)?;
+                }
+            },
+            "if" => {
+                let condition = self.tokens.iter().position(|token| token.text == "then" || token.text == "begin");
+                let begin = condition.is_some_and(|index| self.tokens[index].text == "begin");
+                // `then` guards one statement, so skip it unless the condition holds.
+                // `begin` jumps past the block unless it holds.
+                self.condition(begin)?;
+                let keyword = self.next()?;
+                if !matches!(keyword.text.as_str(), "then" | "begin") {
+                    return Err(self.error(format!("expected then or begin, found {}", keyword.text)));
+                }
+                if begin {
+                    let jump = self.here;
+                    self.emit(Jump(0))?;
+                    self.branches.push(Branch { jump, token });
+                }
+            },
+            "else" => {
+                let Some(open) = self.branches.pop() else {
+                    return Err(self.error("else without a matching begin"));
+                };
+                let jump = self.here;
+                self.emit(Jump(0))?;
+                self.patch(open.jump, Patch::Address, self.here, &token)?;
+                self.branches.push(Branch { jump, token });
+            },
+            "end" => {
+                let Some(open) = self.branches.pop() else {
+                    return Err(self.error("end without a matching begin"));
+                };
+                self.patch(open.jump, Patch::Address, self.here, &token)?;
+            },
+            text if self.register_named(text).is_some() => {
+                let x = self.register_named(text).unwrap();
+                self.assignment(x)?;
+            },
+            text if self.macros.contains_key(text) => self.expand(token)?,
+            text if number(text).is_some() || self.constants.contains_key(text) => {
+                let value = self.value_of(&token)?;
+                let byte = self.byte_value(value)?;
+                self.write_bytes(&[byte])?;
+            },
+            text if text.starts_with(':') || text == "{" || text == "}" => {
+                return Err(self.error(format!("unexpected {}", text)));
+            },
+            _ => self.call(token)?,
+        }
+        Ok(())
+    }
+
+    fn name(&mut self) -> Result<String> {
+        let token = self.next()?;
+        if number(&token.text).is_some() || parse_register(&token.text).is_some() || matches!(token.text.as_str(), "{" | "}" | ":") {
+            return Err(self.error(format!("invalid name {}", token.text)));
+        }
+        Ok(token.text)
+    }
+
+    fn check_undefined(&self, name: &str) -> Result<()> {
+        if self.addresses.contains_key(name) || self.constants.contains_key(name) || self.macros.contains_key(name) {
+            return Err(self.error(format!("{} is already defined", name)));
+        }
+        Ok(())
+    }
+
+    fn define_label(&mut self, name: String, address: u16) -> Result<()> {
+        self.check_undefined(&name)?;
+        self.labels.entry(address).or_insert_with(|| name.clone());
+        self.addresses.insert(name, address);
+        Ok(())
+    }
+
+    fn define_constant(&mut self, name: String, value: f64) -> Result<()> {
+        self.check_undefined(&name)?;
+        self.constants.insert(name, value);
+        Ok(())
+    }
+
+    fn define_macro(&mut self) -> Result<()> {
+        let name = self.name()?;
+        self.check_undefined(&name)?;
+        let mut arguments = Vec::new();
+        loop {
+            let token = self.next()?;
+            if token.text == "{" {
+                break;
+            }
+            arguments.push(token.text);
+        }
+        let body = self.block()?;
+        self.macros.insert(name, Macro { arguments, body });
+        Ok(())
+    }
+
+    // Tokens up to the `}` closing an already read `{`
+    fn block(&mut self) -> Result<Vec<Token>> {
+        let mut body = Vec::new();
+        let mut depth = 0;
+        loop {
+            let token = self.next()?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" if depth == 0 => return Ok(body),
+                "}" => depth -= 1,
+                _ => (),
+            }
+            body.push(token);
+        }
+    }
+
+    fn expand(&mut self, invocation: Token) -> Result<()> {
+        if invocation.depth >= MAX_MACRO_DEPTH {
+            return Err(self.error(format!("macro {} expands too deeply", invocation.text)));
+        }
+        let count = self.macros[&invocation.text].arguments.len();
+        let mut values = Vec::with_capacity(count);
+        for _ in 0..count {
+            values.push(self.next()?.text);
+        }
+        let expansion = &self.macros[&invocation.text];
+        let body: Vec<Token> = expansion
+            .body
+            .iter()
+            .map(|token| {
+                let text = match expansion.arguments.iter().position(|argument| *argument == token.text) {
+                    Some(index) => values[index].clone(),
+                    None => token.text.clone(),
+                };
+                Token { text, depth: invocation.depth + 1, ..token.clone() }
+            })
+            .collect();
+        for token in body.into_iter().rev() {
+            self.tokens.push_front(token);
+        }
+        Ok(())
+    }
+
+    fn register_named(&self, text: &str) -> Option<u8> {
+        parse_register(text).or_else(|| self.aliases.get(text).copied())
+    }
+
+    fn register(&mut self) -> Result<u8> {
+        let token = self.next()?;
+        self.register_named(&token.text).ok_or_else(|| self.error(format!("expected a register, found {}", token.text)))
+    }
+
+    fn value_of(&self, token: &Token) -> Result<f64> {
+        number(&token.text)
+            .or_else(|| self.constants.get(&token.text).copied())
+            .ok_or_else(|| self.error_at(token, format!("expected a number, found {}", token.text)))
+    }
+
+    fn constant(&mut self) -> Result<f64> {
+        let token = self.next()?;
+        match self.addresses.get(&token.text) {
+            Some(&address) => Ok(address as f64),
+            None => self.value_of(&token),
+        }
+    }
+
+    fn bounded(&self, value: f64, bits: u32) -> Result<u16> {
+        let value = value.floor() as i64;
+        let max = (1i64 << bits) - 1;
+        // Negative numbers are two's complement, e.g. -1 for 0xFF
+        if !(-(max + 1) / 2..=max).contains(&value) {
+            return Err(self.error(format!("{} does not fit in {} bits", value, bits)));
+        }
+        Ok((value & max) as u16)
+    }
+
+    fn byte_value(&self, value: f64) -> Result<u8> {
+        Ok(self.bounded(value, 8)? as u8)
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        let value = self.constant()?;
+        self.byte_value(value)
+    }
+
+    fn nibble(&mut self) -> Result<u8> {
+        let value = self.constant()?;
+        Ok(self.bounded(value, 4)? as u8)
+    }
+
+    fn call(&mut self, target: Token) -> Result<()> {
+        let address = self.here;
+        self.emit(Instruction::Call(0))?;
+        self.reference(address, Patch::Address, target)
+    }
+
+    // Patches the instruction at `address` with the address or value of
+    // `target`, now or once the label is defined
+    fn reference(&mut self, address: u16, patch: Patch, target: Token) -> Result<()> {
+        let value = match self.addresses.get(&target.text) {
+            Some(&value) => value,
+            None if number(&target.text).is_some() || self.constants.contains_key(&target.text) => {
+                self.bounded(self.value_of(&target)?, 16)?
+            },
+            None if parse_register(&target.text).is_some() || matches!(target.text.as_str(), "{" | "}") => {
+                return Err(self.error_at(&target, format!("expected an address, found {}", target.text)));
+            },
+            None => {
+                self.fixups.push(Fixup { address, patch, token: target });
+                return Ok(());
+            },
+        };
+        self.patch(address, patch, value, &target)
+    }
+
+    fn patch(&mut self, address: u16, patch: Patch, value: u16, token: &Token) -> Result<()> {
+        let offset = (address - START) as usize;
+        match patch {
+            Patch::Address => {
+                if value > 0xFFF {
+                    return Err(self.error_at(token, format!("address 0x{:X} does not fit in 12 bits", value)));
+                }
+                self.rom[offset] |= (value >> 8) as u8;
+                self.rom[offset + 1] = value as u8;
+            },
+            Patch::Long => {
+                self.rom[offset + 2] = (value >> 8) as u8;
+                self.rom[offset + 3] = value as u8;
+            },
+            Patch::Unpack => {
+                if value > 0xFFF {
+                    return Err(self.error_at(token, format!("address 0x{:X} does not fit in 12 bits", value)));
+                }
+                self.rom[offset + 1] |= (value >> 8) as u8;
+                self.rom[offset + 3] = value as u8;
+            },
+            Patch::UnpackLong => {
+                self.rom[offset + 1] = (value >> 8) as u8;
+                self.rom[offset + 3] = value as u8;
+            },
+        }
+        Ok(())
+    }
+
+    fn index(&mut self) -> Result<()> {
+        use Instruction::*;
+        let operator = self.next()?;
+        match operator.text.as_str() {
+            "+=" => {
+                let x = self.register()?;
+                self.emit(AddIndex(x))
+            },
+            ":=" => match self.peek() {
+                Some("hex") | Some("bighex") => {
+                    let big = self.next()?.text == "bighex";
+                    let x = self.register()?;
+                    self.emit(if big { BigFont(x) } else { Font(x) })
+                },
+                Some("long") => {
+                    self.next()?;
+                    let target = self.next()?;
+                    let address = self.here;
+                    self.emit(SetIndexLong(0))?;
+                    self.reference(address, Patch::Long, target)
+                },
+                _ => {
+                    let target = self.next()?;
+                    let address = self.here;
+                    self.emit(SetIndex(0))?;
+                    self.reference(address, Patch::Address, target)
+                },
+            },
+            other => Err(self.error(format!("expected := or += after i, found {}", other))),
+        }
+    }
+
+    fn assignment(&mut self, x: u8) -> Result<()> {
+        use Instruction::*;
+        let operator = self.next()?;
+        let operand = self.peek().map(str::to_string);
+        let y = operand.as_deref().and_then(|text| self.register_named(text));
+        let instruction = match (operator.text.as_str(), y, operand.as_deref()) {
+            (":=", Some(y), _) => Copy(x, y),
+            (":=", None, Some("random")) => {
+                self.next()?;
+                Random(x, self.byte()?)
+            },
+            (":=", None, Some("key")) => WaitKey(x),
+            (":=", None, Some("delay")) => GetDelay(x),
+            (":=", None, _) => return self.emit_byte_operand(|value| Set(x, value)),
+            ("+=", Some(y), _) => AddRegister(x, y),
+            ("+=", None, _) => return self.emit_byte_operand(|value| Add(x, value)),
+            ("-=", Some(y), _) => Subtract(x, y),
+            ("-=", None, _) => return self.emit_byte_operand(|value| Add(x, value.wrapping_neg())),
+            ("=-", Some(y), _) => SubtractReversed(x, y),
+            ("|=", Some(y), _) => Or(x, y),
+            ("&=", Some(y), _) => And(x, y),
+            ("^=", Some(y), _) => Xor(x, y),
+            (">>=", Some(y), _) => ShiftRight(x, y),
+            ("<<=", Some(y), _) => ShiftLeft(x, y),
+            (operator, ..) => {
+                let operand = operand.unwrap_or_default();
+                return Err(self.error(format!("invalid operands for {}: {}", operator, operand)));
+            },
+        };
+        if !matches!(instruction, Random(..)) {
+            self.next()?;
+        }
+        self.emit(instruction)
+    }
+
+    fn emit_byte_operand(&mut self, instruction: impl FnOnce(u8) -> Instruction) -> Result<()> {
+        let value = self.byte()?;
+        self.emit(instruction(value))
+    }
+
+    // Emits a test that skips the next instruction when the condition read
+    // next equals `skip_when`. Comparisons go through compare-temp, VF
+    // unless aliased elsewhere.
+    fn condition(&mut self, skip_when: bool) -> Result<()> {
+        use Instruction::*;
+        let x = self.register()?;
+        let operator = self.next()?;
+        let instruction = match operator.text.as_str() {
+            // -key holds while the key is up
+            "key" | "-key" => {
+                if skip_when == (operator.text == "key") { SkipIfKey(x) } else { SkipIfNotKey(x) }
+            },
+            "==" | "!=" => {
+                let token = self.next()?;
+                // Skip on equality when `skip_when` and the operator agree
+                let on_equal = skip_when == (operator.text == "==");
+                match self.register_named(&token.text) {
+                    Some(y) if on_equal => SkipIfRegistersEqual(x, y),
+                    Some(y) => SkipIfRegistersNotEqual(x, y),
+                    None => {
+                        let value = self.value_of(&token)?;
+                        let value = self.byte_value(value)?;
+                        if on_equal { SkipIfEqual(x, value) } else { SkipIfNotEqual(x, value) }
+                    },
+                }
+            },
+            "<" | ">" | "<=" | ">=" => {
+                let temp = self.aliases.get("compare-temp").copied().unwrap_or(0xF);
+                let token = self.next()?;
+                match self.register_named(&token.text) {
+                    Some(y) => self.emit(Copy(temp, y))?,
+                    None => {
+                        let value = self.value_of(&token)?;
+                        let value = self.byte_value(value)?;
+                        self.emit(Set(temp, value))?;
+                    },
+                }
+                // VF ends up as the no borrow flag of x - y or y - x, temp
+                // as the difference unless it is VF itself
+                let reversed = matches!(operator.text.as_str(), "<" | ">=");
+                self.emit(if reversed { SubtractReversed(temp, x) } else { Subtract(temp, x) })?;
+                // < and > hold when the flag is clear, <= and >= when it's set
+                let on_clear = matches!(operator.text.as_str(), "<" | ">");
+                if skip_when == on_clear { SkipIfEqual(0xF, 0) } else { SkipIfNotEqual(0xF, 0) }
+            },
+            other => return Err(self.error(format!("invalid condition operator {}", other))),
+        };
+        self.emit(instruction)
+    }
+
+    // Evaluates a `{ ... }` expression. Like Octo, binary operators have no
+    // precedence and group from the right: 2 * 3 + 1 is 8.
+    fn calc(&mut self) -> Result<f64> {
+        let open = self.next()?;
+        if open.text != "{" {
+            return Err(self.error(format!("expected {{, found {}", open.text)));
+        }
+        let tokens = self.block()?;
+        let mut position = 0;
+        let value = self.expression(&tokens, &mut position)?;
+        if let Some(token) = tokens.get(position) {
+            return Err(self.error_at(token, format!("unexpected {} in expression", token.text)));
+        }
+        Ok(value)
+    }
+
+    fn expression(&self, tokens: &[Token], position: &mut usize) -> Result<f64> {
+        let left = self.term(tokens, position)?;
+        let Some(operator) = tokens.get(*position).filter(|token| is_binary_operator(&token.text)) else {
+            return Ok(left);
+        };
+        *position += 1;
+        let right = self.expression(tokens, position)?;
+        let truth = |holds: bool| if holds { 1.0 } else { 0.0 };
+        let integer = |value: f64| value.floor() as i64;
+        Ok(match operator.text.as_str() {
+            "+" => left + right,
+            "-" => left - right,
+            "*" => left * right,
+            "/" => left / right,
+            "%" => left % right,
+            "&" => (integer(left) & integer(right)) as f64,
+            "|" => (integer(left) | integer(right)) as f64,
+            "^" => (integer(left) ^ integer(right)) as f64,
+            "<<" => (integer(left) << integer(right).clamp(0, 63)) as f64,
+            ">>" => (integer(left) >> integer(right).clamp(0, 63)) as f64,
+            "pow" => left.powf(right),
+            "min" => left.min(right),
+            "max" => left.max(right),
+            "<" => truth(left < right),
+            "<=" => truth(left <= right),
+            "==" => truth(left == right),
+            "!=" => truth(left != right),
+            ">=" => truth(left >= right),
+            _ => truth(left > right),
+        })
+    }
+
+    fn term(&self, tokens: &[Token], position: &mut usize) -> Result<f64> {
+        let Some(token) = tokens.get(*position) else {
+            let last = tokens.last().unwrap_or(&self.current);
+            return Err(self.error_at(last, "incomplete expression"));
+        };
+        *position += 1;
+        let unary = |function: fn(f64) -> f64, position: &mut usize| self.term(tokens, position).map(function);
+        match token.text.as_str() {
+            "(" => {
+                let value = self.expression(tokens, position)?;
+                match tokens.get(*position) {
+                    Some(close) if close.text == ")" => {
+                        *position += 1;
+                        Ok(value)
+                    },
+                    _ => Err(self.error_at(token, "unclosed parenthesis")),
+                }
+            },
+            "-" => unary(|value| -value, position),
+            "~" => unary(|value| !(value.floor() as i64) as f64, position),
+            "!" => unary(|value| if value == 0.0 { 1.0 } else { 0.0 }, position),
+            "sin" => unary(f64::sin, position),
+            "cos" => unary(f64::cos, position),
+            "tan" => unary(f64::tan, position),
+            "exp" => unary(f64::exp, position),
+            "log" => unary(f64::ln, position),
+            "abs" => unary(f64::abs, position),
+            "sqrt" => unary(f64::sqrt, position),
+            "sign" => unary(|value| if value == 0.0 { 0.0 } else { value.signum() }, position),
+            "ceil" => unary(f64::ceil, position),
+            "floor" => unary(f64::floor, position),
+            "@" => {
+                let address = self.term(tokens, position)?.floor() as i64;
+                let offset = address - START as i64;
+                Ok(usize::try_from(offset).ok().and_then(|offset| self.rom.get(offset)).copied().unwrap_or(0) as f64)
+            },
+            "HERE" => Ok(self.here as f64),
+            "PI" => Ok(std::f64::consts::PI),
+            "E" => Ok(std::f64::consts::E),
+            text => match self.addresses.get(text) {
+                Some(&address) => Ok(address as f64),
+                None if self.register_named(text).is_some() => Err(self.error_at(token, format!("registers have no value in expressions: {}", text))),
+                None => number(text)
+                    .or_else(|| self.constants.get(text).copied())
+                    .ok_or_else(|| self.error_at(token, format!("undefined name {} in expression", text))),
+            },
+        }
+    }
+
+    fn emit(&mut self, instruction: Instruction) -> Result<()> {
+        let bytes: Vec<u8> = instruction.encode().iter().flat_map(|word| word.to_be_bytes()).collect();
+        self.write_bytes(&bytes)
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<()> {
+        let end = self.here as usize + bytes.len();
+        if end > RAM_SIZE {
+            return Err(self.error(format!("program does not fit in memory, reaching 0x{:X}", end)));
+        }
+        let offset = (self.here - START) as usize;
+        if self.rom.len() < offset + bytes.len() {
+            self.rom.resize(offset + bytes.len(), 0);
+        }
+        self.rom[offset..offset + bytes.len()].copy_from_slice(bytes);
+        self.here = end as u16;
+        Ok(())
+    }
+
+    fn write_word(&mut self, address: u16, word: u16) {
+        let offset = (address - START) as usize;
+        self.rom[offset..offset + 2].copy_from_slice(&word.to_be_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(source: &str) -> Vec<u16> {
+        let rom = compile(source, "test.8o").unwrap_or_else(|error| panic!("{}", error)).rom;
+        rom.chunks(2).map(|pair| u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)])).collect()
+    }
+
+    fn error(source: &str) -> String {
+        compile(source, "test.8o").unwrap_err().to_string()
+    }
+
+    #[test]
+    fn statements_and_labels() {
+        let program = compile(": main\n v0 := 5\n v1 += v0\n i := sprite\n sprite v0 v1 5\n loop again\n: sprite 0xFF", "test.8o").unwrap();
+        assert_eq!(program.rom, [0x60, 0x05, 0x81, 0x04, 0xA2, 0x0A, 0xD0, 0x15, 0x12, 0x08, 0xFF]);
+        assert_eq!(program.labels.get(&0x200).map(String::as_str), Some("main"));
+        assert_eq!(program.labels.get(&0x20A).map(String::as_str), Some("sprite"));
+    }
+
+    #[test]
+    fn main_is_reached_through_a_jump_unless_it_comes_first() {
+        assert_eq!(words(": helper return\n: main helper"), [0x1204, 0x00EE, 0x2202]);
+        // Called before it is defined
+        assert_eq!(words(": main draw\n: draw return"), [0x2202, 0x00EE]);
+    }
+
+    #[test]
+    fn comparisons_test_the_borrow_flag() {
+        assert_eq!(words(": main if v1 < 5 then v2 := 1"), [0x6F05, 0x8F17, 0x4F00, 0x6201]);
+        assert_eq!(words(": main if v1 >= v2 then v3 := 1"), [0x8F20, 0x8F17, 0x3F00, 0x6301]);
+        assert_eq!(words(": main if v1 > 5 then v2 := 1"), [0x6F05, 0x8F15, 0x4F00, 0x6201]);
+        // The difference lands in v0, the flag still in VF
+        assert_eq!(words(":alias compare-temp v0\n: main if v1 < 5 then v2 := 1"), [0x6005, 0x8017, 0x4F00, 0x6201]);
+        assert_eq!(words(":alias compare-temp v0\n: main if v1 <= v2 then v3 := 1"), [0x8020, 0x8015, 0x3F00, 0x6301]);
+    }
+
+    #[test]
+    fn control_flow() {
+        assert_eq!(words(": main if v0 == 1 begin v1 := 2 else v1 := 3 end"), [0x3001, 0x1208, 0x6102, 0x120A, 0x6103]);
+        assert_eq!(words(": main loop v0 += 1 while v0 != 10 again"), [0x7001, 0x400A, 0x1208, 0x1200]);
+        assert_eq!(words(": main if v2 key then v3 := 1"), [0xE2A1, 0x6301]);
+    }
+
+    #[test]
+    fn constants_and_macros() {
+        // No precedence, grouping from the right
+        assert_eq!(words(":const SIZE 4\n:calc DOUBLE { SIZE * 2 + 1 }\n: main v0 := DOUBLE"), [0x600C]);
+        assert_eq!(words(":macro twice reg { reg += 1 reg += 1 }\n: main twice v3"), [0x7301, 0x7301]);
+        assert_eq!(words(": main i := long data\n: data 1 2"), [0xF000, 0x0204, 0x0102]);
+    }
+
+    #[test]
+    fn errors_point_at_the_token() {
+        assert_eq!(error(": main\n  v0 := nowhere"), "test.8o:2:9: expected a number, found nowhere");
+        assert_eq!(error(": main\n  jump missing"), "test.8o:2:8: undefined name missing");
+        assert_eq!(error(": main\n  if v0 ~ 1 then return"), "test.8o:2:9: invalid condition operator ~");
+        assert_eq!(error("v0 := 1"), "test.8o:1:1: program has no main label");
+    }
+}
@@ -0,0 +1,58 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+// Colors for the background, plane 1, plane 2 and both planes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    pub colors: [Color; 4],
+}
+
+impl Color {
+    pub const fn rgb(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+
+    // Parses "RRGGBB", with or without a leading '#'
+    pub fn parse(text: &str) -> Option<Self> {
+        let hex = text.strip_prefix('#').unwrap_or(text);
+        if hex.len() != 6 {
+            return None;
+        }
+        let value = u32::from_str_radix(hex, 16).ok()?;
+        Some(Self::rgb((value >> 16) as u8, (value >> 8) as u8, value as u8))
+    }
+}
+
+impl Palette {
+    pub fn color(&self, pixel: u8) -> Color {
+        self.colors[pixel as usize & 0b11]
+    }
+
+    // Parses a comma separated list of 2 or 4 colors; with 2 colors the
+    // second plane reuses the foreground
+    pub fn parse(text: &str) -> Option<Self> {
+        let colors = text.split(',').map(Color::parse).collect::<Option<Vec<_>>>()?;
+        match colors[..] {
+            [background, foreground] => Some(Self { colors: [background, foreground, foreground, foreground] }),
+            [background, plane1, plane2, both] => Some(Self { colors: [background, plane1, plane2, both] }),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self {
+            colors: [
+                Color::rgb(0xFF, 0xFF, 0xFF),
+                Color::rgb(0x00, 0x00, 0x00),
+                Color::rgb(0xAA, 0xAA, 0xAA),
+                Color::rgb(0x55, 0x55, 0x55),
+            ],
+        }
+    }
+}
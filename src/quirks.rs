@@ -0,0 +1,171 @@
+use crate::cpu::{CLASSIC_RAM_SIZE, RAM_SIZE};
+
+/// Behavior of the opcodes that CHIP-8 implementations disagree on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    // 8XY6/8XYE shift VY into VX instead of shifting VX in place
+    pub shift_uses_vy: bool,
+    // FX55/FX65 leave I pointing after the last register accessed
+    pub load_store_increments_i: bool,
+    // BNNN is read as BXNN and jumps to XNN + VX instead of NNN + V0
+    pub jump_uses_vx: bool,
+    // 8XY1/8XY2/8XY3 reset VF to 0
+    pub logic_resets_vf: bool,
+    // DXYN wraps sprites around the screen edges instead of clipping them
+    pub wrap_sprites: bool,
+    // FX1E sets VF when I moves past 0xFFF
+    pub index_overflow_sets_vf: bool,
+    // 64 KiB of memory as on XO-CHIP instead of the original 4 KiB
+    pub large_memory: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Variant {
+    // Every quirk off, the way this emulator always ran
+    #[default]
+    Modern,
+    Vip,
+    Schip11,
+    XoChip,
+    // Octo's own defaults. Octo runs XO-CHIP programs with the same settings,
+    // so this matches XoChip; both names exist so a ROM can be run with the
+    // preset its author asks for.
+    Octo,
+}
+
+impl Quirks {
+    pub const fn modern() -> Self {
+        Self {
+            shift_uses_vy: false,
+            load_store_increments_i: false,
+            jump_uses_vx: false,
+            logic_resets_vf: false,
+            wrap_sprites: false,
+            index_overflow_sets_vf: false,
+            large_memory: false,
+        }
+    }
+
+    pub const fn vip() -> Self {
+        Self {
+            shift_uses_vy: true,
+            load_store_increments_i: true,
+            jump_uses_vx: false,
+            logic_resets_vf: true,
+            wrap_sprites: false,
+            index_overflow_sets_vf: false,
+            large_memory: false,
+        }
+    }
+
+    pub const fn schip() -> Self {
+        Self {
+            shift_uses_vy: false,
+            load_store_increments_i: false,
+            jump_uses_vx: true,
+            logic_resets_vf: false,
+            wrap_sprites: false,
+            index_overflow_sets_vf: true,
+            large_memory: false,
+        }
+    }
+
+    pub const fn xochip() -> Self {
+        Self {
+            shift_uses_vy: true,
+            load_store_increments_i: true,
+            jump_uses_vx: false,
+            logic_resets_vf: false,
+            wrap_sprites: true,
+            index_overflow_sets_vf: false,
+            large_memory: true,
+        }
+    }
+
+    pub const fn octo() -> Self {
+        Self {
+            shift_uses_vy: true,
+            load_store_increments_i: true,
+            jump_uses_vx: false,
+            logic_resets_vf: false,
+            wrap_sprites: true,
+            index_overflow_sets_vf: false,
+            large_memory: true,
+        }
+    }
+}
+
+impl Quirks {
+    pub fn to_bits(self) -> u8 {
+        [
+            self.shift_uses_vy,
+            self.load_store_increments_i,
+            self.jump_uses_vx,
+            self.logic_resets_vf,
+            self.wrap_sprites,
+            self.index_overflow_sets_vf,
+            self.large_memory,
+        ]
+        .iter()
+        .enumerate()
+        .fold(0, |bits, (i, &set)| bits | ((set as u8) << i))
+    }
+
+    pub fn from_bits(bits: u8) -> Self {
+        let set = |i: u8| bits & (1 << i) != 0;
+        Self {
+            shift_uses_vy: set(0),
+            load_store_increments_i: set(1),
+            jump_uses_vx: set(2),
+            logic_resets_vf: set(3),
+            wrap_sprites: set(4),
+            index_overflow_sets_vf: set(5),
+            large_memory: set(6),
+        }
+    }
+
+    // Bytes of RAM the machine has
+    pub fn memory_size(self) -> usize {
+        if self.large_memory { RAM_SIZE } else { CLASSIC_RAM_SIZE }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Variant::default().quirks()
+    }
+}
+
+impl Variant {
+    pub const ALL: [Variant; 5] = [Variant::Modern, Variant::Vip, Variant::Schip11, Variant::XoChip, Variant::Octo];
+
+    pub fn quirks(self) -> Quirks {
+        match self {
+            Variant::Modern => Quirks::modern(),
+            Variant::Vip => Quirks::vip(),
+            Variant::Schip11 => Quirks::schip(),
+            Variant::XoChip => Quirks::xochip(),
+            Variant::Octo => Quirks::octo(),
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|variant| variant.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Variant::Modern => "modern",
+            Variant::Vip => "vip",
+            Variant::Schip11 => "schip",
+            Variant::XoChip => "xochip",
+            Variant::Octo => "octo",
+        }
+    }
+}
+
+impl From<Variant> for Quirks {
+    fn from(variant: Variant) -> Self {
+        variant.quirks()
+    }
+}
@@ -0,0 +1,209 @@
+use std::collections::VecDeque;
+
+// Recent machine states, newest last. Only the newest state is kept whole;
+// every older one is stored as the run-length encoded XOR against its
+// successor, which is mostly zeros since RAM rarely changes between frames.
+pub struct Rewind {
+    capacity: usize,
+    current: Option<Vec<u8>>,
+    deltas: VecDeque<Vec<u8>>,
+}
+
+impl Rewind {
+    // `capacity` is the number of states kept, i.e. frames of history
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            current: None,
+            deltas: VecDeque::new(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    pub fn len(&self) -> usize {
+        self.deltas.len() + self.current.is_some() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.current.is_none()
+    }
+
+    // Bytes used by the history, to keep an eye on the compression
+    pub fn memory_usage(&self) -> usize {
+        self.deltas.iter().map(Vec::len).sum::<usize>() + self.current.as_ref().map_or(0, Vec::len)
+    }
+
+    pub fn clear(&mut self) {
+        self.current = None;
+        self.deltas.clear();
+    }
+
+    pub fn push(&mut self, state: Vec<u8>) {
+        if self.capacity == 0 {
+            return;
+        }
+        if let Some(previous) = self.current.take() {
+            self.deltas.push_back(encode_delta(&previous, &state));
+            if self.deltas.len() >= self.capacity {
+                self.deltas.pop_front();
+            }
+        }
+        self.current = Some(state);
+    }
+
+    // Drops the newest state and returns the one before it, or None once the
+    // oldest state is reached
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let delta = self.deltas.pop_back()?;
+        let current = self.current.take()?;
+        let previous = decode_delta(&current, &delta);
+        self.current = Some(previous.clone());
+        Some(previous)
+    }
+}
+
+fn push_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(bytes: &[u8], position: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    while let Some(&byte) = bytes.get(*position) {
+        *position += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+    }
+    value
+}
+
+// Length of `previous`, then (zero run, literal run, literal bytes) groups of
+// previous XOR next, with the shorter state padded with zeros
+fn encode_delta(previous: &[u8], next: &[u8]) -> Vec<u8> {
+    let len = previous.len().max(next.len());
+    let xor = |i: usize| previous.get(i).copied().unwrap_or(0) ^ next.get(i).copied().unwrap_or(0);
+    let mut out = Vec::new();
+    push_varint(&mut out, previous.len());
+    let mut i = 0;
+    while i < len {
+        let zeros_start = i;
+        while i < len && xor(i) == 0 {
+            i += 1;
+        }
+        let literal_start = i;
+        while i < len && xor(i) != 0 {
+            i += 1;
+        }
+        push_varint(&mut out, literal_start - zeros_start);
+        push_varint(&mut out, i - literal_start);
+        out.extend((literal_start..i).map(xor));
+    }
+    out
+}
+
+fn decode_delta(next: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut position = 0;
+    let len = read_varint(delta, &mut position);
+    let mut previous = next.to_vec();
+    previous.resize(len.max(next.len()), 0);
+    let mut i = 0;
+    while position < delta.len() {
+        i += read_varint(delta, &mut position);
+        let literals = read_varint(delta, &mut position);
+        for &byte in &delta[position..position + literals] {
+            previous[i] ^= byte;
+            i += 1;
+        }
+        position += literals;
+    }
+    previous.truncate(len);
+    previous
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::CPU;
+    use crate::quirks::Variant;
+
+    // A state where a few bytes change every frame, like RAM does, and whose
+    // length changes now and then
+    fn state(frame: usize) -> Vec<u8> {
+        (0..256 + frame % 3 * 16).map(|i| if i % 37 == frame % 37 { frame as u8 } else { (i / 16) as u8 }).collect()
+    }
+
+    #[test]
+    fn restores_every_state() {
+        let mut rewind = Rewind::new(100);
+        for frame in 0..50 {
+            rewind.push(state(frame));
+        }
+        assert_eq!(rewind.len(), 50);
+        for frame in (0..49).rev() {
+            assert_eq!(rewind.pop(), Some(state(frame)), "frame {}", frame);
+        }
+        assert_eq!(rewind.pop(), None);
+        assert_eq!(rewind.len(), 1);
+    }
+
+    #[test]
+    fn keeps_the_newest_states_up_to_capacity() {
+        let mut rewind = Rewind::new(10);
+        for frame in 0..35 {
+            rewind.push(state(frame));
+            assert!(rewind.len() <= 10);
+        }
+        for frame in (25..34).rev() {
+            assert_eq!(rewind.pop(), Some(state(frame)), "frame {}", frame);
+        }
+        assert_eq!(rewind.pop(), None);
+
+        // History goes on from the state rewound to, wrapping the ring again
+        for frame in 100..125 {
+            rewind.push(state(frame));
+        }
+        assert_eq!(rewind.len(), 10);
+        for frame in (115..124).rev() {
+            assert_eq!(rewind.pop(), Some(state(frame)), "frame {}", frame);
+        }
+        assert_eq!(rewind.pop(), None);
+    }
+
+    #[test]
+    fn restores_cpu_snapshots() {
+        let mut cpu = CPU::with_seed(Variant::Vip.quirks(), 0);
+        cpu.load_ram(concat!(env!("CARGO_MANIFEST_DIR"), "/rom/ibm_logo.ch8")).unwrap();
+        let mut rewind = Rewind::new(30);
+        let mut saved = Vec::new();
+        for _ in 0..40 {
+            for _ in 0..3 {
+                cpu.cycle().unwrap();
+            }
+            saved.push(cpu.save_state());
+            rewind.push(cpu.save_state());
+        }
+        for expected in saved[10..39].iter().rev() {
+            assert!(rewind.pop().as_ref() == Some(expected));
+        }
+        assert_eq!(rewind.pop(), None);
+    }
+
+    #[test]
+    fn zero_capacity_keeps_nothing() {
+        let mut rewind = Rewind::new(0);
+        rewind.push(state(0));
+        rewind.push(state(1));
+        assert!(rewind.is_empty());
+        assert_eq!(rewind.pop(), None);
+    }
+}
@@ -0,0 +1,137 @@
+// Source of the random bytes returned by CXNN
+pub trait RandomSource: Send {
+    fn next_byte(&mut self) -> u8;
+    // Opaque generator state, restored by `restore` to resume the same sequence
+    fn state(&self) -> Vec<u8>;
+    fn restore(&mut self, state: &[u8]) -> bool;
+}
+
+// xorshift64* seeded through splitmix64, so every seed (including 0) is usable
+#[derive(Debug, Clone)]
+pub struct XorShiftRng {
+    state: u64,
+}
+
+impl XorShiftRng {
+    pub fn new(seed: u64) -> Self {
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        Self { state: if z == 0 { 1 } else { z } }
+    }
+}
+
+impl RandomSource for XorShiftRng {
+    fn next_byte(&mut self) -> u8 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        (self.state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8
+    }
+
+    fn state(&self) -> Vec<u8> {
+        self.state.to_le_bytes().to_vec()
+    }
+
+    fn restore(&mut self, state: &[u8]) -> bool {
+        match <[u8; 8]>::try_from(state) {
+            Ok(bytes) if bytes != [0; 8] => {
+                self.state = u64::from_le_bytes(bytes);
+                true
+            },
+            _ => false,
+        }
+    }
+}
+
+// Cycles through a fixed list of bytes, for tests
+#[derive(Debug, Clone)]
+pub struct SequenceRng {
+    bytes: Vec<u8>,
+    position: usize,
+}
+
+impl SequenceRng {
+    pub fn new(bytes: Vec<u8>) -> Self {
+        Self { bytes, position: 0 }
+    }
+}
+
+impl RandomSource for SequenceRng {
+    fn next_byte(&mut self) -> u8 {
+        if self.bytes.is_empty() {
+            return 0;
+        }
+        let byte = self.bytes[self.position % self.bytes.len()];
+        self.position = (self.position + 1) % self.bytes.len();
+        byte
+    }
+
+    fn state(&self) -> Vec<u8> {
+        (self.position as u64).to_le_bytes().to_vec()
+    }
+
+    fn restore(&mut self, state: &[u8]) -> bool {
+        match <[u8; 8]>::try_from(state) {
+            Ok(bytes) => {
+                self.position = u64::from_le_bytes(bytes) as usize;
+                true
+            },
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::CPU;
+    use crate::quirks::Variant;
+
+    // Values CXNN puts in V0 over `count` runs
+    fn draws(cpu: &mut CPU, opcode: u16, count: usize) -> Vec<u8> {
+        (0..count)
+            .map(|_| {
+                cpu.next_instruction(opcode).unwrap();
+                cpu.registers()[0]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_sequence() {
+        for seed in [0, 1, 0xDEAD_BEEF] {
+            let mut first = CPU::with_seed(Variant::Vip.quirks(), seed);
+            let mut second = CPU::with_seed(Variant::Vip.quirks(), seed);
+            let sequence = draws(&mut first, 0xC0FF, 64);
+            assert_eq!(draws(&mut second, 0xC0FF, 64), sequence);
+            assert!(sequence.iter().any(|&byte| byte != sequence[0]), "seed {} gives a constant", seed);
+        }
+        let mut first = CPU::with_seed(Variant::Vip.quirks(), 1);
+        let mut second = CPU::with_seed(Variant::Vip.quirks(), 2);
+        assert_ne!(draws(&mut first, 0xC0FF, 64), draws(&mut second, 0xC0FF, 64));
+    }
+
+    #[test]
+    fn cxnn_masks_the_injected_source() {
+        let mut cpu = CPU::with_seed(Variant::Vip.quirks(), 0);
+        cpu.set_rng(Box::new(SequenceRng::new(vec![0xFF, 0xA5, 0x3C])));
+        assert_eq!(draws(&mut cpu, 0xC0FF, 4), [0xFF, 0xA5, 0x3C, 0xFF]);
+        assert_eq!(draws(&mut cpu, 0xC00F, 3), [0x05, 0x0C, 0x0F]);
+        assert_eq!(draws(&mut cpu, 0xC000, 2), [0x00, 0x00]);
+    }
+
+    #[test]
+    fn restores_its_position() {
+        let mut rng = XorShiftRng::new(5);
+        rng.next_byte();
+        let state = rng.state();
+        let ahead: Vec<u8> = (0..8).map(|_| rng.next_byte()).collect();
+        let mut restored = XorShiftRng::new(0);
+        assert!(restored.restore(&state));
+        assert_eq!((0..8).map(|_| restored.next_byte()).collect::<Vec<u8>>(), ahead);
+        assert!(!restored.restore(&[0; 8]));
+        assert!(!restored.restore(&[1; 4]));
+    }
+}
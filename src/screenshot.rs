@@ -0,0 +1,105 @@
+use std::fmt::Write;
+
+use crate::cpu::{Framebuffer, CPU};
+use crate::error::{Error, Result};
+use crate::palette::Palette;
+
+// Characters for a pixel: off, lit on the first plane, the second or both
+const PIXELS: [char; 4] = ['.', '#', '+', '@'];
+
+// The visible screen as text, one line per row and one character per pixel
+pub fn text(cpu: &CPU) -> String {
+    text_grid(cpu.vram(), cpu.width(), cpu.height())
+}
+
+pub fn text_grid(vram: &Framebuffer, width: usize, height: usize) -> String {
+    let mut text = String::with_capacity((width + 1) * height);
+    for row in &vram[..height] {
+        text.extend(row[..width].iter().map(|&pixel| PIXELS[(pixel & 3) as usize]));
+        text.push('\n');
+    }
+    text
+}
+
+// Describes how two text grids differ: each differing row from both, with
+// the differing columns marked under them. None when they're equal.
+pub fn text_diff(expected: &str, found: &str) -> Option<String> {
+    if expected == found {
+        return None;
+    }
+    let (expected_rows, found_rows): (Vec<&str>, Vec<&str>) = (expected.lines().collect(), found.lines().collect());
+    let mut out = String::new();
+    let size = |rows: &[&str]| format!("{}x{}", rows.first().map_or(0, |row| row.chars().count()), rows.len());
+    if size(&expected_rows) != size(&found_rows) {
+        writeln!(out, "expected a {} screen, found {}", size(&expected_rows), size(&found_rows)).unwrap();
+    }
+    let mut pixels = 0;
+    for row in 0..expected_rows.len().max(found_rows.len()) {
+        let (expected_row, found_row) = (expected_rows.get(row).copied().unwrap_or(""), found_rows.get(row).copied().unwrap_or(""));
+        if expected_row == found_row {
+            continue;
+        }
+        let (expected_pixels, found_pixels): (Vec<char>, Vec<char>) = (expected_row.chars().collect(), found_row.chars().collect());
+        let marks: String = (0..expected_pixels.len().max(found_pixels.len()))
+            .map(|column| if expected_pixels.get(column) == found_pixels.get(column) { ' ' } else { '^' })
+            .collect();
+        pixels += marks.matches('^').count();
+        writeln!(out, "{:3} expected {}", row, expected_row).unwrap();
+        writeln!(out, "    found    {}", found_row).unwrap();
+        writeln!(out, "             {}", marks.trim_end()).unwrap();
+    }
+    writeln!(out, "{} pixels differ", pixels).unwrap();
+    Some(out)
+}
+
+// Encodes the visible screen as an indexed PNG with the palette's colors,
+// each CHIP-8 pixel becoming a `scale` x `scale` square
+pub fn png(vram: &Framebuffer, width: usize, height: usize, palette: &Palette, scale: u32) -> Result<Vec<u8>> {
+    let scale = scale.max(1) as usize;
+    let pixels = scaled(&visible(vram, width, height), width, scale);
+    let mut bytes = Vec::new();
+    let mut writer = encoder(&mut bytes, width * scale, height * scale, palette).write_header().map_err(encoding)?;
+    writer.write_image_data(&pixels).map_err(encoding)?;
+    writer.finish().map_err(encoding)?;
+    Ok(bytes)
+}
+
+pub fn save_png(cpu: &CPU, palette: &Palette, scale: u32, path: &str) -> Result<()> {
+    let bytes = png(cpu.vram(), cpu.width(), cpu.height(), palette, scale)?;
+    std::fs::write(path, bytes)?;
+    Ok(())
+}
+
+// Palette indices of the visible pixels, row by row
+pub(crate) fn visible(vram: &Framebuffer, width: usize, height: usize) -> Vec<u8> {
+    vram[..height].iter().flat_map(|row| row[..width].iter().map(|&pixel| pixel & 3)).collect()
+}
+
+// Repeats each pixel of a `width` wide image `scale` times in both directions
+pub(crate) fn scaled(pixels: &[u8], width: usize, scale: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(pixels.len() * scale * scale);
+    for row in pixels.chunks(width) {
+        let line: Vec<u8> = row.iter().flat_map(|&pixel| std::iter::repeat_n(pixel, scale)).collect();
+        for _ in 0..scale {
+            out.extend_from_slice(&line);
+        }
+    }
+    out
+}
+
+// An 8-bit indexed PNG encoder with the palette's colors
+pub(crate) fn encoder<W: std::io::Write>(out: W, width: usize, height: usize, palette: &Palette) -> png::Encoder<'static, W> {
+    let colors: Vec<u8> = palette.colors.iter().flat_map(|color| [color.r, color.g, color.b]).collect();
+    let mut encoder = png::Encoder::new(out, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_palette(colors);
+    encoder
+}
+
+pub(crate) fn encoding(error: png::EncodingError) -> Error {
+    match error {
+        png::EncodingError::IoError(error) => Error::Io(error),
+        error => Error::Io(std::io::Error::other(error)),
+    }
+}
@@ -0,0 +1,222 @@
+use std::path::Path;
+
+use crate::cpu::CPU;
+use crate::error::{Error, Result};
+
+const MAGIC: &[u8; 4] = b"C8SS";
+pub const VERSION: u16 = 2;
+
+// 64-bit FNV-1a, used to tie save states and movies to a ROM
+pub fn hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xCBF2_9CE4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01B3)
+    })
+}
+
+#[derive(Default)]
+pub struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn bytes(&mut self, value: &[u8]) {
+        self.bytes.extend_from_slice(value);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.bytes.len() < len {
+            return Err(Error::InvalidSnapshot("unexpected end of data".to_string()));
+        }
+        let (head, tail) = self.bytes.split_at(len);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    pub fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        Ok(self.bytes(N)?.try_into().unwrap())
+    }
+
+    pub fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    pub fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    pub fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    pub fn rest(&mut self) -> &'a [u8] {
+        std::mem::take(&mut self.bytes)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+}
+
+// A save state file: magic, format version, ROM hash, then the machine state
+pub fn encode(cpu: &CPU) -> Vec<u8> {
+    let mut writer = Writer::new();
+    writer.bytes(MAGIC);
+    writer.u16(VERSION);
+    writer.u64(cpu.rom_hash());
+    writer.bytes(&cpu.save_state());
+    writer.finish()
+}
+
+pub fn decode(cpu: &mut CPU, bytes: &[u8]) -> Result<()> {
+    let mut reader = Reader::new(bytes);
+    if reader.bytes(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+        return Err(Error::InvalidSnapshot("not a save state file".to_string()));
+    }
+    let version = reader.u16()?;
+    if version != VERSION {
+        return Err(Error::InvalidSnapshot(format!("unsupported version {}", version)));
+    }
+    let rom_hash = reader.u64()?;
+    if rom_hash != cpu.rom_hash() {
+        return Err(Error::RomMismatch { expected: cpu.rom_hash(), found: rom_hash });
+    }
+    cpu.load_state(reader.rest())
+}
+
+pub fn save(cpu: &CPU, path: impl AsRef<Path>) -> Result<()> {
+    std::fs::write(path, encode(cpu))?;
+    Ok(())
+}
+
+pub fn load(cpu: &mut CPU, path: impl AsRef<Path>) -> Result<()> {
+    let bytes = std::fs::read(path)?;
+    decode(cpu, &bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quirks::Variant;
+
+    const ROM: [u8; 4] = [0x12, 0x00, 0xAB, 0xCD];
+
+    fn machine(rom: &[u8]) -> CPU {
+        let mut cpu = CPU::with_seed(Variant::Vip.quirks(), 7);
+        cpu.load_rom(rom).unwrap();
+        cpu
+    }
+
+    // Something in every part of the state: registers, I, the stack,
+    // timers, memory written through I, the screen and the random source
+    fn busy_machine() -> CPU {
+        let mut cpu = machine(&ROM);
+        for opcode in [0x60C8, 0x6133, 0x6205, 0xF015, 0xF118, 0xA300, 0xF033, 0xA303, 0xF255, 0xA000, 0xD125, 0xC3FF, 0x2202] {
+            cpu.next_instruction(opcode).unwrap();
+        }
+        cpu.set_key(0xA, true);
+        cpu
+    }
+
+    #[test]
+    fn round_trips_the_machine() {
+        let cpu = busy_machine();
+        let mut loaded = machine(&ROM);
+        decode(&mut loaded, &encode(&cpu)).unwrap();
+
+        assert_eq!(loaded.pc(), 0x202);
+        assert_eq!(loaded.pc(), cpu.pc());
+        assert_eq!(loaded.registers(), cpu.registers());
+        assert_eq!(loaded.index(), 0x0000);
+        assert_eq!(loaded.stack(), &[0x218]);
+        assert_eq!((loaded.delay_timer(), loaded.sound_timer()), (0xC8, 0x33));
+        assert_eq!(&loaded.ram()[0x300..0x306], &[2, 0, 0, 0xC8, 0x33, 0x05]);
+        assert_eq!(loaded.ram(), cpu.ram());
+        assert!(loaded.vram() == cpu.vram());
+        assert_eq!(loaded.vram()[5][0x33], 1);
+        assert_eq!(loaded.keys(), cpu.keys());
+        assert_eq!(loaded.save_state(), cpu.save_state());
+
+        // The random source carries on from where it was
+        let (mut cpu, mut loaded) = (cpu, loaded);
+        for _ in 0..8 {
+            cpu.next_instruction(0xC4FF).unwrap();
+            loaded.next_instruction(0xC4FF).unwrap();
+            assert_eq!(loaded.registers()[4], cpu.registers()[4]);
+        }
+    }
+
+    #[test]
+    fn refuses_states_of_another_rom() {
+        let saved = encode(&busy_machine());
+        let mut other = machine(&[0x12, 0x00]);
+        let before = other.save_state();
+        assert!(matches!(decode(&mut other, &saved), Err(Error::RomMismatch { .. })));
+        assert_eq!(other.save_state(), before);
+    }
+
+    #[test]
+    fn rejects_damaged_states_without_changing_the_machine() {
+        let saved = encode(&busy_machine());
+        let mut bad_version = saved.clone();
+        bad_version[4..6].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        let mut bad_magic = saved.clone();
+        bad_magic[0] = b'X';
+        let mut trailing = saved.clone();
+        trailing.push(0);
+        let damaged = [&saved[..saved.len() - 1], &saved[..20], &saved[..3], &bad_version, &bad_magic, &trailing];
+
+        let mut cpu = machine(&ROM);
+        cpu.next_instruction(0x6A42).unwrap();
+        let before = cpu.save_state();
+        for bytes in damaged {
+            assert!(matches!(decode(&mut cpu, bytes), Err(Error::InvalidSnapshot(_))), "{} bytes", bytes.len());
+            assert_eq!(cpu.save_state(), before);
+        }
+    }
+}
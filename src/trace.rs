@@ -0,0 +1,208 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+use crate::error::Result;
+use crate::watch;
+
+// Identifies the line format in the header, so traces made by builds that
+// format them differently aren't diffed against each other by mistake
+pub const FORMAT_VERSION: u32 = 1;
+
+// Which instructions get written. Empty lists let everything through.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TraceFilter {
+    // Inclusive PC ranges
+    pub ranges: Vec<(u16, u16)>,
+    // Cowgod mnemonics, e.g. DRW or LD
+    pub mnemonics: Vec<String>,
+}
+
+impl TraceFilter {
+    // Parses START[-END]
+    pub fn parse_range(text: &str) -> Option<(u16, u16)> {
+        let (start, end) = text.split_once('-').unwrap_or((text, text));
+        let (start, end) = (watch::parse_address(start)?, watch::parse_address(end)?);
+        (start <= end).then_some((start, end))
+    }
+
+    // Parses a comma separated list of mnemonics
+    pub fn parse_mnemonics(text: &str) -> Option<Vec<String>> {
+        let mnemonics: Vec<String> = text.split(',').map(|mnemonic| mnemonic.trim().to_ascii_uppercase()).collect();
+        (!mnemonics.iter().any(String::is_empty)).then_some(mnemonics)
+    }
+
+    pub fn accepts(&self, pc: u16, text: &str) -> bool {
+        let mnemonic = text.split_whitespace().next().unwrap_or("");
+        (self.ranges.is_empty() || self.ranges.iter().any(|&(start, end)| (start..=end).contains(&pc)))
+            && (self.mnemonics.is_empty() || self.mnemonics.iter().any(|wanted| wanted == mnemonic))
+    }
+}
+
+// The registers an instruction can change, captured before and after it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct State {
+    pub registers: [u8; 16],
+    pub index: u16,
+}
+
+// Writes one line per executed instruction:
+//
+//   cycle pc opcode instruction I=.... V=<V0..VF> -> I=.... V=<V0..VF>
+//
+// with fixed width columns, so two traces of the same ROM can be diffed
+// line by line. The cycle counts filtered out instructions too.
+pub struct Tracer {
+    out: Box<dyn Write>,
+    filter: TraceFilter,
+    cycles: u64,
+}
+
+impl Tracer {
+    pub fn new(mut out: Box<dyn Write>, filter: TraceFilter) -> io::Result<Self> {
+        writeln!(out, "# chip8-emulator trace v{}", FORMAT_VERSION)?;
+        writeln!(out, "# cycle pc opcode instruction I/V before -> I/V after")?;
+        Ok(Self { out, filter, cycles: 0 })
+    }
+
+    pub fn create(path: &str, filter: TraceFilter) -> Result<Self> {
+        let file = File::create(path)?;
+        Ok(Self::new(Box::new(BufWriter::new(file)), filter)?)
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    // `text` is the disassembly of the instruction, taken before it ran
+    pub fn record(&mut self, pc: u16, opcode: u16, text: &str, before: State, after: State) -> io::Result<()> {
+        self.cycles += 1;
+        if !self.filter.accepts(pc, text) {
+            return Ok(());
+        }
+        writeln!(
+            self.out,
+            "{:>10} {:04X} {:04X} {:<24} I={:04X} V={} -> I={:04X} V={}",
+            self.cycles,
+            pc,
+            opcode,
+            text,
+            before.index,
+            hex(&before.registers),
+            after.index,
+            hex(&after.registers)
+        )
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+// One line of a trace, parsed back
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Step {
+    pub cycle: u64,
+    pub pc: u16,
+    pub opcode: u16,
+    pub text: String,
+    pub before: State,
+    pub after: State,
+}
+
+impl Step {
+    pub fn parse(line: &str) -> Option<Self> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let [cycle, pc, opcode, text @ .., before_index, before_registers, "->", after_index, after_registers] = words.as_slice() else {
+            return None;
+        };
+        Some(Self {
+            cycle: cycle.parse().ok()?,
+            pc: u16::from_str_radix(pc, 16).ok()?,
+            opcode: u16::from_str_radix(opcode, 16).ok()?,
+            text: text.join(" "),
+            before: parse_state(before_index, before_registers)?,
+            after: parse_state(after_index, after_registers)?,
+        })
+    }
+
+    // What differs from `expected`, one item per line
+    fn differences(&self, expected: &Step) -> Vec<String> {
+        let mut differences = Vec::new();
+        let mut compare = |name: String, expected: u16, found: u16, width: usize| {
+            if expected != found {
+                differences.push(format!("{}: expected {:0width$X}, found {:0width$X}", name, expected, found, width = width));
+            }
+        };
+        compare("PC".to_string(), expected.pc, self.pc, 4);
+        compare("opcode".to_string(), expected.opcode, self.opcode, 4);
+        for (when, expected, found) in [("before", expected.before, self.before), ("after", expected.after, self.after)] {
+            compare(format!("I {}", when), expected.index, found.index, 4);
+            for register in 0..16 {
+                compare(format!("V{:X} {}", register, when), expected.registers[register] as u16, found.registers[register] as u16, 2);
+            }
+        }
+        differences
+    }
+}
+
+fn parse_state(index: &str, registers: &str) -> Option<State> {
+    let index = u16::from_str_radix(index.strip_prefix("I=")?, 16).ok()?;
+    let registers = registers.strip_prefix("V=")?;
+    if registers.len() != 32 {
+        return None;
+    }
+    let mut values = [0; 16];
+    for (register, value) in values.iter_mut().enumerate() {
+        *value = u8::from_str_radix(registers.get(register * 2..register * 2 + 2)?, 16).ok()?;
+    }
+    Some(State { registers: values, index })
+}
+
+// The first line where two traces differ, or where one of them ends early
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    // 1-based
+    pub line: usize,
+    pub expected: Option<String>,
+    pub found: Option<String>,
+}
+
+// Compares a trace against a reference, e.g. a golden trace checked in
+// next to the tests
+pub fn first_divergence(expected: &str, found: &str) -> Option<Divergence> {
+    let (mut expected_lines, mut found_lines) = (expected.lines(), found.lines());
+    let mut line = 0;
+    loop {
+        line += 1;
+        match (expected_lines.next(), found_lines.next()) {
+            (None, None) => return None,
+            (Some(expected), Some(found)) if expected == found => continue,
+            (expected, found) => {
+                return Some(Divergence { line, expected: expected.map(str::to_string), found: found.map(str::to_string) });
+            },
+        }
+    }
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let step = |line: &Option<String>| line.as_deref().and_then(Step::parse);
+        match step(&self.expected) {
+            Some(expected) => writeln!(f, "traces diverge at cycle {} (line {})", expected.cycle, self.line)?,
+            None => writeln!(f, "traces diverge at line {}", self.line)?,
+        }
+        writeln!(f, "  expected: {}", self.expected.as_deref().unwrap_or("end of trace"))?;
+        writeln!(f, "  found:    {}", self.found.as_deref().unwrap_or("end of trace"))?;
+        if let (Some(expected), Some(found)) = (step(&self.expected), step(&self.found)) {
+            for difference in found.differences(&expected) {
+                writeln!(f, "  {}", difference)?;
+            }
+        }
+        Ok(())
+    }
+}
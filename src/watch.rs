@@ -0,0 +1,246 @@
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use crate::disasm;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Region {
+    Addresses(u16, u16),
+    // The bytes the ROM was loaded into
+    Program,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchAction {
+    Log,
+    Break,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub region: Region,
+    pub reads: bool,
+    pub writes: bool,
+    pub action: WatchAction,
+}
+
+// A watched access, made by the instruction `opcode` at `pc`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hit {
+    pub watchpoint: usize,
+    pub action: WatchAction,
+    pub pc: u16,
+    pub opcode: u16,
+    pub address: u16,
+    pub value: u8,
+    pub access: Access,
+    pub self_modifying: bool,
+}
+
+impl Watchpoint {
+    // Parses START[-END][:r|w|rw], or program[:r|w|rw] for the ROM's bytes.
+    // Address ranges default to reads and writes, the program to writes.
+    pub fn parse(text: &str, action: WatchAction) -> Option<Self> {
+        let (range, access) = match text.split_once(':') {
+            Some((range, access)) => (range, Some(access)),
+            None => (text, None),
+        };
+        let region = if range.eq_ignore_ascii_case("program") {
+            Region::Program
+        } else {
+            let (start, end) = range.split_once('-').unwrap_or((range, range));
+            let (start, end) = (parse_address(start)?, parse_address(end)?);
+            if end < start {
+                return None;
+            }
+            Region::Addresses(start, end)
+        };
+        let (reads, writes) = match (access.map(str::to_ascii_lowercase).as_deref(), region) {
+            (None, Region::Program) => (false, true),
+            (None | Some("rw"), _) => (true, true),
+            (Some("r"), _) => (true, false),
+            (Some("w"), _) => (false, true),
+            _ => return None,
+        };
+        Some(Self { region, reads, writes, action })
+    }
+
+    fn matches(&self, address: u16, access: Access, program: &RangeInclusive<u16>) -> bool {
+        let inside = match self.region {
+            Region::Addresses(start, end) => (start..=end).contains(&address),
+            Region::Program => program.contains(&address),
+        };
+        inside && (if access == Access::Read { self.reads } else { self.writes })
+    }
+}
+
+pub(crate) fn parse_address(text: &str) -> Option<u16> {
+    let lower = text.trim().to_ascii_lowercase();
+    match lower.strip_prefix("0x").or_else(|| lower.strip_prefix('#')) {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => lower.parse().ok(),
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.region {
+            Region::Addresses(start, end) if start == end => write!(f, "0x{:04X}", start)?,
+            Region::Addresses(start, end) => write!(f, "0x{:04X}-0x{:04X}", start, end)?,
+            Region::Program => write!(f, "program")?,
+        }
+        let access = match (self.reads, self.writes) {
+            (true, true) => "rw",
+            (true, false) => "r",
+            _ => "w",
+        };
+        write!(f, ":{}", access)
+    }
+}
+
+impl fmt::Display for Hit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let verb = match self.access {
+            Access::Read => "read",
+            Access::Write => "written",
+        };
+        write!(
+            f,
+            "watchpoint {}: 0x{:04X} {} (0x{:02X}) by 0x{:04X}: {:04X} {}",
+            self.watchpoint + 1,
+            self.address,
+            verb,
+            self.value,
+            self.pc,
+            self.opcode,
+            disasm::mnemonic(self.opcode)
+        )?;
+        if self.self_modifying {
+            write!(f, " [self-modifying code]")?;
+        }
+        Ok(())
+    }
+}
+
+// Watchpoints on the memory accessed through I. Hits are collected as the
+// CPU runs and taken by whoever drives it, which logs them or breaks.
+#[derive(Debug, Default)]
+pub struct Watchpoints {
+    watchpoints: Vec<Watchpoint>,
+    hits: Vec<Hit>,
+}
+
+impl Watchpoints {
+    pub fn is_empty(&self) -> bool {
+        self.watchpoints.is_empty()
+    }
+
+    pub fn list(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    pub fn add(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<Watchpoint> {
+        (index < self.watchpoints.len()).then(|| self.watchpoints.remove(index))
+    }
+
+    pub fn clear(&mut self) {
+        self.watchpoints.clear();
+    }
+
+    pub fn check(&mut self, pc: u16, opcode: u16, address: u16, value: u8, access: Access, program: RangeInclusive<u16>) {
+        for (index, watchpoint) in self.watchpoints.iter().enumerate() {
+            if watchpoint.matches(address, access, &program) {
+                self.hits.push(Hit {
+                    watchpoint: index,
+                    action: watchpoint.action,
+                    pc,
+                    opcode,
+                    address,
+                    value,
+                    access,
+                    self_modifying: access == Access::Write && program.contains(&address),
+                });
+            }
+        }
+    }
+
+    pub fn take_hits(&mut self) -> Vec<Hit> {
+        std::mem::take(&mut self.hits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::CPU;
+    use crate::quirks::Variant;
+
+    #[test]
+    fn parses_watch_syntax() {
+        let watch = |text| Watchpoint::parse(text, WatchAction::Log);
+        let region = |text| watch(text).map(|watchpoint| (watchpoint.region, watchpoint.reads, watchpoint.writes));
+        assert_eq!(region("0x300"), Some((Region::Addresses(0x300, 0x300), true, true)));
+        assert_eq!(region("#300-#30F:r"), Some((Region::Addresses(0x300, 0x30F), true, false)));
+        assert_eq!(region("768-784:W"), Some((Region::Addresses(768, 784), false, true)));
+        assert_eq!(region("0x300:rw"), Some((Region::Addresses(0x300, 0x300), true, true)));
+        assert_eq!(region("program"), Some((Region::Program, false, true)));
+        assert_eq!(region("PROGRAM:r"), Some((Region::Program, true, false)));
+        assert_eq!(watch("0x300").unwrap().to_string(), "0x0300:rw");
+        assert_eq!(watch("0x300-0x310:w").unwrap().to_string(), "0x0300-0x0310:w");
+        assert_eq!(Watchpoint::parse("0x300", WatchAction::Break).unwrap().action, WatchAction::Break);
+        for bad in ["", "0x310-0x300", "0x300:x", "0x10000", "zz", "program:"] {
+            assert_eq!(watch(bad), None, "{:?}", bad);
+        }
+    }
+
+    // Runs `opcodes` with I at 0x300 and everything from there watched,
+    // returning the addresses and kinds of the accesses seen
+    fn accesses(variant: Variant, opcodes: &[u16]) -> Vec<(u16, Access)> {
+        let mut cpu = CPU::with_seed(variant.quirks(), 0);
+        cpu.load_rom(&[0; 0x200]).unwrap();
+        cpu.watchpoints_mut().add(Watchpoint::parse("0x300-0x3FF", WatchAction::Log).unwrap());
+        cpu.next_instruction(0xA300).unwrap();
+        for &opcode in opcodes {
+            cpu.next_instruction(opcode).unwrap();
+        }
+        cpu.watchpoints_mut().take_hits().iter().map(|hit| (hit.address, hit.access)).collect()
+    }
+
+    fn range(start: u16, len: u16, access: Access) -> Vec<(u16, Access)> {
+        (start..start + len).map(|address| (address, access)).collect()
+    }
+
+    #[test]
+    fn reports_one_hit_per_byte_accessed() {
+        assert_eq!(accesses(Variant::Vip, &[0xD005]), range(0x300, 5, Access::Read));
+        // Sprites clipped at the right edge still read each row once
+        assert_eq!(accesses(Variant::Vip, &[0x603C, 0xD003]), range(0x300, 3, Access::Read));
+        // A 16x16 sprite is two bytes a row
+        assert_eq!(accesses(Variant::Schip11, &[0x00FF, 0xD000]), range(0x300, 32, Access::Read));
+        assert_eq!(accesses(Variant::Vip, &[0xF255]), range(0x300, 3, Access::Write));
+        assert_eq!(accesses(Variant::Vip, &[0xF265]), range(0x300, 3, Access::Read));
+    }
+
+    #[test]
+    fn flags_writes_into_the_program() {
+        let mut cpu = CPU::with_seed(Variant::Vip.quirks(), 0);
+        cpu.load_rom(&[0; 0x10]).unwrap();
+        cpu.watchpoints_mut().add(Watchpoint::parse("program", WatchAction::Break).unwrap());
+        for opcode in [0xA208, 0xF065, 0xA208, 0xF055, 0xA210, 0xF055] {
+            cpu.next_instruction(opcode).unwrap();
+        }
+        let hits = cpu.watchpoints_mut().take_hits();
+        assert_eq!(hits.len(), 1);
+        assert_eq!((hits[0].address, hits[0].opcode, hits[0].self_modifying), (0x208, 0xF055, true));
+    }
+}
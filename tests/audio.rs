@@ -0,0 +1,50 @@
+// Renders the bundled audio test ROM into an in-memory WAV file and checks
+// that each frame's 1/60 s of samples is sounding exactly while the sound
+// timer ran, apart from the release fading out, so the sound lines up with
+// emulated time.
+
+use std::io::Cursor;
+
+use chip8_emulator::buzzer::{Tone, SAMPLE_RATE};
+use chip8_emulator::cpu::CPU;
+use chip8_emulator::emulator::{ClockMode, Emulator};
+use chip8_emulator::frontend::null::{NullInput, NullVideo};
+use chip8_emulator::frontend::wav::{WavAudio, SAMPLES_PER_FRAME};
+use chip8_emulator::quirks::Variant;
+
+const FRAMES: u64 = 300;
+const HEADER_SIZE: usize = 44;
+
+#[test]
+fn test_audio() {
+    let wav = WavAudio::new(Cursor::new(Vec::new()), Tone::default()).unwrap();
+    let mut emulator = Emulator::new(CPU::with_seed(Variant::Vip.quirks(), 0), NullVideo, wav, NullInput);
+    emulator.set_clock_mode(ClockMode::Emulated);
+    emulator.load_program(&format!("{}/rom/test-audio.ch8", env!("CARGO_MANIFEST_DIR"))).unwrap();
+    let mut sounding = Vec::new();
+    for _ in 0..FRAMES {
+        assert!(emulator.step_frame().unwrap());
+        sounding.push(emulator.cpu().sound_active());
+    }
+    emulator.finish_audio().unwrap();
+    let (_, _, wav, _) = emulator.into_parts();
+    assert_eq!(wav.samples() as usize, FRAMES as usize * SAMPLES_PER_FRAME);
+    let bytes = wav.into_inner().into_inner();
+
+    assert_eq!(&bytes[..4], b"RIFF");
+    assert_eq!(&bytes[8..16], b"WAVEfmt ");
+    let data_size = u32::from_le_bytes(bytes[40..44].try_into().unwrap()) as usize;
+    assert_eq!(data_size, bytes.len() - HEADER_SIZE);
+    assert_eq!(data_size, FRAMES as usize * SAMPLES_PER_FRAME * 2);
+
+    let samples: Vec<i16> = bytes[HEADER_SIZE..].chunks(2).map(|pair| i16::from_le_bytes([pair[0], pair[1]])).collect();
+    let release = (Tone::default().release * SAMPLE_RATE as f32).ceil() as usize;
+    for (frame, (chunk, &sounding)) in samples.chunks(SAMPLES_PER_FRAME).zip(&sounding).enumerate() {
+        if sounding {
+            assert!(chunk.iter().all(|&sample| sample != 0), "frame {} should sound", frame);
+        } else {
+            assert!(chunk[release..].iter().all(|&sample| sample == 0), "frame {} should be silent", frame);
+        }
+    }
+    assert!(sounding.contains(&true), "the ROM never beeped");
+}
@@ -0,0 +1,91 @@
+// Checks the buzzer's envelope and that its oscillator runs on across
+// buffers and gate changes, so the sound neither clicks nor restarts.
+
+use chip8_emulator::buzzer::{Buzzer, Tone, Waveform};
+
+const RATE: u32 = 8000;
+
+fn tone(waveform: Waveform, attack: f32, release: f32) -> Tone {
+    Tone { frequency: 100.0, waveform, volume: 0.5, attack, release }
+}
+
+fn samples(buzzer: &mut Buzzer, count: usize) -> Vec<f32> {
+    let mut out = vec![0.0; count];
+    buzzer.fill(&mut out);
+    out
+}
+
+#[test]
+fn ramps_up_and_down() {
+    // 10 ms at 8 kHz: 80 samples each way
+    let mut buzzer = Buzzer::new(RATE, tone(Waveform::Square, 0.01, 0.01));
+    buzzer.set_gate(true);
+    let attack = samples(&mut buzzer, 100);
+    for (i, sample) in attack.iter().enumerate() {
+        let level = ((i + 1) as f32 / 80.0).min(1.0);
+        assert!((sample.abs() - 0.5 * level).abs() < 1e-4, "sample {}: {}", i, sample);
+    }
+
+    buzzer.set_gate(false);
+    let release = samples(&mut buzzer, 100);
+    for (i, sample) in release.iter().enumerate() {
+        let level = (1.0 - (i + 1) as f32 / 80.0).max(0.0);
+        assert!((sample.abs() - 0.5 * level).abs() < 1e-4, "sample {}: {}", i, sample);
+    }
+    assert!(release[80..].iter().all(|&sample| sample == 0.0));
+}
+
+#[test]
+fn zero_envelope_switches_instantly() {
+    let mut buzzer = Buzzer::new(RATE, tone(Waveform::Square, 0.0, 0.0));
+    buzzer.set_gate(true);
+    assert!(samples(&mut buzzer, 50).iter().all(|sample| sample.abs() == 0.5));
+    buzzer.set_gate(false);
+    assert!(samples(&mut buzzer, 50).iter().all(|&sample| sample == 0.0));
+}
+
+#[test]
+fn plays_the_tone_frequency() {
+    let mut buzzer = Buzzer::new(RATE, tone(Waveform::Sine, 0.0, 0.0));
+    buzzer.set_gate(true);
+    let one_second = samples(&mut buzzer, RATE as usize);
+    let rises = one_second.windows(2).filter(|pair| pair[0] < 0.0 && pair[1] >= 0.0).count();
+    assert!((99..=100).contains(&rises), "{} periods", rises);
+}
+
+#[test]
+fn keeps_its_phase_across_buffers() {
+    for waveform in Waveform::ALL {
+        let mut whole = Buzzer::new(RATE, tone(waveform, 0.005, 0.005));
+        whole.set_gate(true);
+        let expected = samples(&mut whole, 1000);
+
+        let mut pieces = Buzzer::new(RATE, tone(waveform, 0.005, 0.005));
+        pieces.set_gate(true);
+        let mut found = Vec::new();
+        for size in [1, 7, 133, 300, 559] {
+            found.extend(samples(&mut pieces, size));
+        }
+        assert_eq!(found, expected, "{}", waveform.name());
+    }
+}
+
+#[test]
+fn keeps_its_phase_while_silent() {
+    // A beep that stops and starts again picks up the wave where a beep that
+    // never stopped would be, rather than restarting it
+    let mut steady = Buzzer::new(RATE, tone(Waveform::Sine, 0.0, 0.0));
+    steady.set_gate(true);
+    let expected = samples(&mut steady, 300);
+
+    let mut gated = Buzzer::new(RATE, tone(Waveform::Sine, 0.0, 0.0));
+    gated.set_gate(true);
+    let mut found = samples(&mut gated, 100);
+    gated.set_gate(false);
+    found.extend(samples(&mut gated, 37));
+    gated.set_gate(true);
+    found.extend(samples(&mut gated, 163));
+    assert_eq!(found[..100], expected[..100]);
+    assert!(found[100..137].iter().all(|&sample| sample == 0.0));
+    assert_eq!(found[137..], expected[137..]);
+}
@@ -0,0 +1,95 @@
+// Records screens into animated PNG clips and decodes them again, checking
+// the frame count, that each frame lasts as many 1/60 s as its screen was
+// shown and that lores frames fill a clip that switched to hires.
+
+use chip8_emulator::clip::Clip;
+use chip8_emulator::cpu::{Framebuffer, CPU};
+use chip8_emulator::emulator::{ClockMode, Emulator};
+use chip8_emulator::frontend::null::{NullAudio, NullInput, NullVideo};
+use chip8_emulator::palette::Palette;
+use chip8_emulator::quirks::Variant;
+
+struct Frame {
+    delay: (u16, u16),
+    pixels: Vec<u8>,
+}
+
+// Size, loop count and frames of an animated PNG
+fn decode(bytes: &[u8]) -> ((u32, u32), u32, Vec<Frame>) {
+    let mut reader = png::Decoder::new(bytes).read_info().unwrap();
+    let size = (reader.info().width, reader.info().height);
+    let animation = reader.info().animation_control.unwrap();
+    let mut frames = Vec::new();
+    for _ in 0..animation.num_frames {
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut pixels).unwrap();
+        let control = reader.info().frame_control.unwrap();
+        assert_eq!((control.width, control.height), size);
+        frames.push(Frame { delay: (control.delay_num, control.delay_den), pixels });
+    }
+    (size, animation.num_plays, frames)
+}
+
+fn screen(lit: &[(usize, usize)]) -> Framebuffer {
+    let mut vram = [[0; 128]; 64];
+    for &(x, y) in lit {
+        vram[y][x] = 1;
+    }
+    vram
+}
+
+#[test]
+fn merges_repeated_screens_into_longer_frames() {
+    let (first, second) = (screen(&[(0, 0)]), screen(&[(63, 31)]));
+    let mut clip = Clip::new();
+    for vram in [&first, &first, &first, &second, &first, &first] {
+        clip.push(vram, 64, 32);
+    }
+    assert_eq!(clip.len(), 6);
+
+    let (size, plays, frames) = decode(&clip.encode(&Palette::default(), 2).unwrap());
+    assert_eq!(size, (128, 64));
+    assert_eq!(plays, 0, "clips loop forever");
+    let delays: Vec<(u16, u16)> = frames.iter().map(|frame| frame.delay).collect();
+    assert_eq!(delays, [(3, 60), (1, 60), (2, 60)]);
+    // Each pixel is a 2x2 square
+    let lit = |frame: &Frame| frame.pixels.iter().enumerate().filter(|(_, &pixel)| pixel != 0).map(|(index, _)| (index % 128, index / 128)).collect::<Vec<_>>();
+    assert_eq!(lit(&frames[0]), [(0, 0), (1, 0), (0, 1), (1, 1)]);
+    assert_eq!(lit(&frames[1]), [(126, 62), (127, 62), (126, 63), (127, 63)]);
+}
+
+#[test]
+fn scales_lores_frames_up_to_hires() {
+    let mut clip = Clip::new();
+    clip.push(&screen(&[(1, 1)]), 64, 32);
+    clip.push(&screen(&[(1, 1)]), 128, 64);
+    let (size, _, frames) = decode(&clip.encode(&Palette::default(), 1).unwrap());
+    assert_eq!(size, (128, 64));
+    assert_eq!(frames.len(), 2);
+    let lit = |frame: &Frame| frame.pixels.iter().filter(|&&pixel| pixel != 0).count();
+    assert_eq!((lit(&frames[0]), lit(&frames[1])), (4, 1));
+    assert_eq!(frames[0].pixels[2 * 128 + 2], 1);
+}
+
+#[test]
+fn records_every_emulated_frame() {
+    let mut emulator = Emulator::new(CPU::with_seed(Variant::Vip.quirks(), 0), NullVideo, NullAudio, NullInput);
+    emulator.set_clock_mode(ClockMode::Emulated);
+    emulator.load_program(&format!("{}/rom/ibm_logo.ch8", env!("CARGO_MANIFEST_DIR"))).unwrap();
+    emulator.set_instructions_per_frame(2);
+    emulator.start_clip();
+    emulator.run_unthrottled(Some(30)).unwrap();
+    let clip = emulator.take_clip().unwrap();
+    assert_eq!(clip.len(), 30);
+
+    let (_, _, frames) = decode(&clip.encode(&Palette::default(), 1).unwrap());
+    assert!(frames.len() > 1, "the logo is drawn over several frames");
+    assert!(frames.iter().all(|frame| frame.delay.1 == 60));
+    assert_eq!(frames.iter().map(|frame| frame.delay.0 as u64).sum::<u64>(), 30);
+    assert!(frames.windows(2).all(|pair| pair[0].pixels != pair[1].pixels), "repeated screens are merged");
+}
+
+#[test]
+fn refuses_empty_clips() {
+    assert!(Clip::new().encode(&Palette::default(), 1).is_err());
+}
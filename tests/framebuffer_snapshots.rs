@@ -0,0 +1,133 @@
+// Runs each bundled ROM on the emulated clock without input and compares the
+// screen against a text grid in tests/snapshots, `#` marking lit pixels. Test
+// ROMs run with the quirks they were written for and must show their pass
+// banner, so an error screen can't become the snapshot. After an intended
+// change, rerun with UPDATE_GOLDEN=1 to rewrite the grids and review their
+// diff.
+
+use std::path::Path;
+
+use chip8_emulator::cpu::CPU;
+use chip8_emulator::emulator::{ClockMode, Emulator};
+use chip8_emulator::frontend::null::{NullAudio, NullInput, NullVideo};
+use chip8_emulator::quirks::Variant;
+use chip8_emulator::screenshot;
+
+// Two seconds, long enough for the test ROMs to print their results
+const FRAMES: u64 = 120;
+const SEED: u64 = 0;
+
+fn check(rom: &str, variant: Variant) {
+    check_passing(rom, variant, &[]);
+}
+
+// Whether `pattern` appears somewhere on the screen, its rows one above the
+// other
+fn shows(screen: &str, pattern: &[&str]) -> bool {
+    let rows: Vec<&str> = screen.lines().collect();
+    rows.windows(pattern.len()).any(|window| {
+        (0..window[0].len()).any(|column| window.iter().zip(pattern).all(|(row, wanted)| row.get(column..column + wanted.len()) == Some(*wanted)))
+    })
+}
+
+// Like `check`, also requiring the screen to show `banner`
+fn check_passing(rom: &str, variant: Variant, banner: &[&str]) {
+    let mut emulator = Emulator::new(CPU::with_seed(variant.quirks(), SEED), NullVideo, NullAudio, NullInput);
+    emulator.set_clock_mode(ClockMode::Emulated);
+    emulator.load_program(&format!("{}/rom/{}", env!("CARGO_MANIFEST_DIR"), rom)).unwrap();
+    emulator.run_unthrottled(Some(FRAMES)).unwrap();
+    let found = screenshot::text(emulator.cpu());
+    if !banner.is_empty() && !shows(&found, banner) {
+        panic!("{} did not pass, its screen lacks\n{}\n\n{}", rom, banner.join("\n"), found);
+    }
+
+    let snapshot = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/snapshots").join(format!("{}.txt", rom));
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::write(&snapshot, found).unwrap();
+        return;
+    }
+    let expected = std::fs::read_to_string(&snapshot)
+        .unwrap_or_else(|error| panic!("cannot read {} ({}), run with UPDATE_GOLDEN=1 to create it", snapshot.display(), error));
+    if let Some(diff) = screenshot::text_diff(&expected, &found) {
+        panic!("{} screen differs from {}\n{}", rom, snapshot.display(), diff);
+    }
+}
+
+#[test]
+fn landing() {
+    check("Landing.ch8", Variant::Vip);
+}
+
+#[test]
+fn pong() {
+    check("PONG", Variant::Vip);
+}
+
+#[test]
+fn sctest() {
+    check_passing("SCTEST", Variant::Schip11, &[
+        "####.#..#",
+        "#..#.#.#.",
+        "#..#.##..",
+        "#..#.#.#.",
+        "####.#..#",
+    ]);
+}
+
+#[test]
+fn tetris() {
+    check("TETRIS", Variant::Vip);
+}
+
+#[test]
+fn bc_test() {
+    // Written for SCHIP shifts and loads; shows E and an error number on failure
+    check_passing("bc_test.ch8", Variant::Schip11, &[
+        "####.....####...#....#",
+        "#...#...#....#..##...#",
+        "#...#...#....#..#.#..#",
+        "####....#....#..#..#.#",
+        "#...#...#....#..#...##",
+        "#...#...#....#..#....#",
+        "#...#...#....#..#....#",
+        "####.....####...#....#",
+    ]);
+}
+
+#[test]
+fn c8_test() {
+    // Needs every quirk off; shows an error number on failure
+    check_passing("c8_test.c8", Variant::Modern, &[
+        ".##....#..#",
+        "#..#...#.#.",
+        "#..#...##..",
+        "#..#...#.#.",
+        ".##....#..#",
+    ]);
+}
+
+#[test]
+fn delay() {
+    check("delay.ch8", Variant::Vip);
+}
+
+#[test]
+fn ibm_logo() {
+    check("ibm_logo.ch8", Variant::Vip);
+}
+
+// Relies on the SCHIP load/store quirk
+#[test]
+fn keys() {
+    check("keys.ch8", Variant::Schip11);
+}
+
+#[test]
+fn test_audio() {
+    check("test-audio.ch8", Variant::Vip);
+}
+
+#[test]
+fn test_opcode() {
+    check("test_opcode.ch8", Variant::Vip);
+}
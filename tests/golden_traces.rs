@@ -0,0 +1,121 @@
+// Runs the bundled test ROMs on the emulated clock, each with the quirks it
+// was written for, and compares every instruction against a golden trace in
+// tests/golden. A run has to end in the ROM's pass state, so a golden can't
+// lock in a failure. After an intended behavior change, rerun with
+// UPDATE_GOLDEN=1 to rewrite the traces and review their diff.
+
+use std::path::{Path, PathBuf};
+
+use chip8_emulator::cpu::CPU;
+use chip8_emulator::emulator::{ClockMode, Emulator};
+use chip8_emulator::frontend::null::{NullAudio, NullInput, NullVideo};
+use chip8_emulator::quirks::Variant;
+use chip8_emulator::trace::{self, Step, TraceFilter, Tracer};
+
+// Far more than any of the ROMs needs to finish
+const MAX_FRAMES: u64 = 600;
+const SEED: u64 = 0;
+
+// How a test ROM shows that it passed
+struct Pass {
+    variant: Variant,
+    // The loop it ends in, jumping to itself
+    end: u16,
+    // Whether an instruction reports a failure
+    failure: fn(&Step) -> bool,
+}
+
+struct Run {
+    trace: String,
+    result: chip8_emulator::error::Result<()>,
+    pc: u16,
+}
+
+// Runs until the ROM settles in a loop jumping to itself, keeping the trace
+// up to that jump, or up to the error if emulation failed
+fn run(rom: &str, variant: Variant) -> Run {
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("{}.trace", rom));
+    let mut emulator = Emulator::new(CPU::with_seed(variant.quirks(), SEED), NullVideo, NullAudio, NullInput);
+    emulator.set_clock_mode(ClockMode::Emulated);
+    emulator.load_program(&format!("{}/rom/{}", env!("CARGO_MANIFEST_DIR"), rom)).unwrap();
+    let tracer = Tracer::create(path.to_str().unwrap(), TraceFilter::default()).unwrap();
+    emulator.cpu_mut().set_tracer(Some(tracer));
+    let settled = |cpu: &CPU| cpu.fetch_opcode() == 0x1000 | cpu.pc();
+    let mut result = Ok(());
+    while emulator.frames() < MAX_FRAMES && !settled(emulator.cpu()) {
+        if let Err(error) = emulator.step_frame() {
+            result = Err(error);
+            break;
+        }
+    }
+    emulator.cpu_mut().take_tracer().unwrap().flush().unwrap();
+    let full = std::fs::read_to_string(path).unwrap();
+    let mut trace = String::new();
+    for line in full.lines() {
+        trace.push_str(line);
+        trace.push('\n');
+        if Step::parse(line).is_some_and(|step| step.opcode == 0x1000 | step.pc) {
+            break;
+        }
+    }
+    Run { trace, result, pc: emulator.cpu().pc() }
+}
+
+// Why the run doesn't count as a pass, if it doesn't
+fn failure(run: &Run, pass: &Pass) -> Option<String> {
+    if let Err(error) = &run.result {
+        return Some(format!("emulation failed: {}", error));
+    }
+    let failed = run.trace.lines().filter_map(Step::parse).find(pass.failure);
+    if let Some(step) = failed {
+        return Some(format!("reported a failure at cycle {}: {:04X} {}", step.cycle, step.pc, step.text));
+    }
+    if run.pc != pass.end {
+        return Some(format!("ended at {:04X} instead of the passing loop at {:04X}", run.pc, pass.end));
+    }
+    None
+}
+
+fn check(rom: &str, pass: Pass) {
+    let run = run(rom, pass.variant);
+    let golden = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden").join(format!("{}.trace", rom));
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        if let Some(reason) = failure(&run, &pass) {
+            panic!("{} did not pass, not writing its trace: {}", rom, reason);
+        }
+        std::fs::write(&golden, &run.trace).unwrap();
+        return;
+    }
+    let expected = std::fs::read_to_string(&golden)
+        .unwrap_or_else(|error| panic!("cannot read {} ({}), run with UPDATE_GOLDEN=1 to create it", golden.display(), error));
+    if let Some(divergence) = trace::first_divergence(&expected, &run.trace) {
+        panic!("{} differs from {}\n{}", rom, golden.display(), divergence);
+    }
+    if let Some(reason) = failure(&run, &pass) {
+        panic!("{} did not pass: {}", rom, reason);
+    }
+}
+
+#[test]
+fn test_opcode() {
+    // Draws the NO sprite at 0x206 instead of OK next to a failed opcode
+    check("test_opcode.ch8", Pass { variant: Variant::Vip, end: 0x3DC, failure: |step| step.opcode >> 12 == 0xD && step.before.index == 0x206 });
+}
+
+#[test]
+fn bc_test() {
+    // Written for SCHIP shifts and loads; prints BON, or E and an error
+    // number from 0x310
+    check("bc_test.ch8", Pass { variant: Variant::Schip11, end: 0x30E, failure: |step| step.pc == 0x310 });
+}
+
+#[test]
+fn c8_test() {
+    // Needs every quirk off; prints OK, or an error number from 0x392
+    check("c8_test.c8", Pass { variant: Variant::Modern, end: 0x386, failure: |step| step.pc == 0x392 });
+}
+
+#[test]
+fn ibm_logo() {
+    check("ibm_logo.ch8", Pass { variant: Variant::Vip, end: 0x228, failure: |_| false });
+}
@@ -0,0 +1,112 @@
+// Records movies of scripted runs and checks that replaying them reaches
+// the same machine state, so a movie can stand in for a regression test.
+
+use chip8_emulator::cpu::CPU;
+use chip8_emulator::debugger::Debugger;
+use chip8_emulator::emulator::{ClockMode, Emulator};
+use chip8_emulator::frontend::null::{NullAudio, NullInput, NullVideo};
+use chip8_emulator::frontend::scripted::ScriptedInput;
+use chip8_emulator::frontend::{Command, InputSource};
+use chip8_emulator::movie::{Movie, MoviePlayer};
+use chip8_emulator::quirks::Variant;
+
+const SEED: u64 = 0;
+
+fn rom(name: &str) -> String {
+    format!("{}/rom/{}", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+fn emulator<I: InputSource>(rom_name: &str, variant: Variant, input: I) -> Emulator<NullVideo, NullAudio, I> {
+    let mut emulator = Emulator::new(CPU::with_seed(variant.quirks(), SEED), NullVideo, NullAudio, input);
+    emulator.set_clock_mode(ClockMode::Emulated);
+    emulator.load_program(&rom(rom_name)).unwrap();
+    emulator
+}
+
+// Replays `movie` from power-on, returning the state it ends in
+fn replay(rom_name: &str, movie: &Movie) -> (u64, Vec<u8>) {
+    let movie = Movie::decode(&movie.encode()).unwrap();
+    let mut emulator = Emulator::new(CPU::with_seed(movie.quirks, movie.seed), NullVideo, NullAudio, MoviePlayer::new(movie.clone(), NullInput));
+    emulator.set_clock_mode(ClockMode::Emulated);
+    emulator.set_instructions_per_frame(movie.instructions_per_frame);
+    emulator.load_program(&rom(rom_name)).unwrap();
+    assert_eq!(emulator.cpu().rom_hash(), movie.rom_hash);
+    emulator.run_unthrottled(None).unwrap();
+    (emulator.frames(), emulator.cpu().save_state())
+}
+
+#[test]
+fn replays_recorded_keys() {
+    let input = ScriptedInput::new([]).hold(0, 20).hold(1 << 0x5, 10).hold(0, 15).hold(1 << 0xA | 1 << 0x3, 25).hold(0, 30);
+    let mut recording = emulator("keys.ch8", Variant::Schip11, input);
+    recording.start_recording();
+    recording.run_unthrottled(None).unwrap();
+    let movie = recording.take_recording().unwrap();
+    assert_eq!(movie.frames.len() as u64, recording.frames());
+
+    let mut idle = emulator("keys.ch8", Variant::Schip11, NullInput);
+    idle.run_unthrottled(Some(recording.frames())).unwrap();
+    assert_ne!(idle.cpu().save_state(), recording.cpu().save_state(), "the keys should change the run");
+
+    assert_eq!(replay("keys.ch8", &movie), (recording.frames(), recording.cpu().save_state()));
+}
+
+#[test]
+fn debugger_break_ends_recording() {
+    // 0x226 first runs as instruction 20, the fourth of frame 5
+    let mut recording = emulator("ibm_logo.ch8", Variant::Vip, NullInput);
+    recording.set_instructions_per_frame(4);
+    let mut debugger = Debugger::new();
+    debugger.add_breakpoint(0x226, None);
+    debugger.execute("continue", recording.cpu_mut());
+    recording.set_debugger(debugger);
+    recording.start_recording();
+    for _ in 0..10 {
+        recording.step_frame().unwrap();
+    }
+    let movie = recording.take_recording().unwrap();
+    assert_eq!(movie.frames.len(), 4);
+
+    let mut plain = emulator("ibm_logo.ch8", Variant::Vip, NullInput);
+    plain.set_instructions_per_frame(4);
+    plain.run_unthrottled(Some(4)).unwrap();
+    assert_eq!(replay("ibm_logo.ch8", &movie), (4, plain.cpu().save_state()));
+}
+
+// Saves state 1 before frame 3 and loads it back before frame 6
+struct SaveAndLoad {
+    polls: u32,
+}
+
+impl InputSource for SaveAndLoad {
+    fn poll(&mut self, _keys: &mut [bool; 16]) -> Command {
+        self.polls += 1;
+        match self.polls {
+            3 => Command::SaveState(1),
+            6 => Command::LoadState(1),
+            10 => Command::Quit,
+            _ => Command::Continue,
+        }
+    }
+}
+
+#[test]
+fn loading_a_state_ends_recording() {
+    let directory = std::env::temp_dir().join(format!("chip8-movies-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let path = directory.join("ibm_logo.ch8");
+    std::fs::copy(rom("ibm_logo.ch8"), &path).unwrap();
+    let mut recording = Emulator::new(CPU::with_seed(Variant::Vip.quirks(), SEED), NullVideo, NullAudio, SaveAndLoad { polls: 0 });
+    recording.set_instructions_per_frame(4);
+    recording.load_program(path.to_str().unwrap()).unwrap();
+    recording.start_recording();
+    recording.run_unthrottled(None).unwrap();
+    std::fs::remove_dir_all(&directory).unwrap();
+    let movie = recording.take_recording().unwrap();
+    assert_eq!(movie.frames.len(), 5);
+
+    let mut plain = emulator("ibm_logo.ch8", Variant::Vip, NullInput);
+    plain.set_instructions_per_frame(4);
+    plain.run_unthrottled(Some(5)).unwrap();
+    assert_eq!(replay("ibm_logo.ch8", &movie), (5, plain.cpu().save_state()));
+}
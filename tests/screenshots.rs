@@ -0,0 +1,61 @@
+// Saves PNG screenshots of the IBM logo and decodes them again, checking
+// their size, palette and that every pixel matches the emulated screen.
+
+use std::path::PathBuf;
+
+use chip8_emulator::cpu::CPU;
+use chip8_emulator::emulator::{ClockMode, Emulator};
+use chip8_emulator::frontend::null::{NullAudio, NullInput, NullVideo};
+use chip8_emulator::palette::Palette;
+use chip8_emulator::quirks::Variant;
+
+// Width, height, palette and indexed pixels of a PNG
+fn decode(bytes: &[u8]) -> (u32, u32, Vec<u8>, Vec<u8>) {
+    let mut reader = png::Decoder::new(bytes).read_info().unwrap();
+    let info = reader.info();
+    assert_eq!((info.color_type, info.bit_depth), (png::ColorType::Indexed, png::BitDepth::Eight));
+    let (width, height) = (info.width, info.height);
+    let palette = info.palette.as_ref().unwrap().to_vec();
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    reader.next_frame(&mut pixels).unwrap();
+    (width, height, palette, pixels)
+}
+
+#[test]
+fn saves_the_screen() {
+    let mut emulator = Emulator::new(CPU::with_seed(Variant::Vip.quirks(), 0), NullVideo, NullAudio, NullInput);
+    emulator.set_clock_mode(ClockMode::Emulated);
+    emulator.load_program(&format!("{}/rom/ibm_logo.ch8", env!("CARGO_MANIFEST_DIR"))).unwrap();
+    emulator.run_unthrottled(Some(60)).unwrap();
+    let palette = Palette::parse("102030,F0E0D0").unwrap();
+    emulator.set_screenshot_options(palette, 3);
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("ibm_logo.png");
+    emulator.save_screenshot(path.to_str().unwrap()).unwrap();
+
+    let (width, height, colors, pixels) = decode(&std::fs::read(path).unwrap());
+    assert_eq!((width, height), (64 * 3, 32 * 3));
+    assert_eq!(colors[..6], [0x10, 0x20, 0x30, 0xF0, 0xE0, 0xD0]);
+    let vram = emulator.cpu().vram();
+    for (index, &pixel) in pixels.iter().enumerate() {
+        let (x, y) = (index % width as usize, index / width as usize);
+        assert_eq!(pixel, vram[y / 3][x / 3], "pixel {},{}", x, y);
+    }
+    assert!(pixels.contains(&1), "the logo should be on screen");
+}
+
+#[test]
+fn saves_both_planes_in_hires() {
+    let mut cpu = CPU::with_seed(Variant::XoChip.quirks(), 0);
+    // Hires, the 0 glyph on plane 1 at (0, 0) and on plane 2 at (8, 0), then
+    // on both planes at (16, 0), where plane 2 takes the 1 glyph after it
+    let program: [u16; 10] = [0x00FF, 0x6000, 0x6108, 0x6210, 0xA000, 0xD005, 0xF201, 0xD105, 0xF301, 0xD205];
+    for opcode in program {
+        cpu.next_instruction(opcode).unwrap();
+    }
+    let bytes = chip8_emulator::screenshot::png(cpu.vram(), cpu.width(), cpu.height(), &Palette::default(), 1).unwrap();
+    let (width, height, colors, pixels) = decode(&bytes);
+    assert_eq!((width, height), (128, 64));
+    assert_eq!(colors.len(), 4 * 3);
+    let at = |x: usize, y: usize| pixels[y * 128 + x];
+    assert_eq!([at(0, 0), at(8, 0), at(16, 0), at(18, 0), at(1, 1)], [1, 2, 1, 3, 0]);
+}
@@ -0,0 +1,75 @@
+// Traces the IBM logo with and without filters and checks that a filtered
+// trace is exactly the matching lines of the full one, cycle numbers
+// included, so the two can still be lined up.
+
+use std::path::PathBuf;
+
+use chip8_emulator::cpu::CPU;
+use chip8_emulator::emulator::{ClockMode, Emulator};
+use chip8_emulator::frontend::null::{NullAudio, NullInput, NullVideo};
+use chip8_emulator::quirks::Variant;
+use chip8_emulator::trace::{Step, TraceFilter, Tracer};
+
+const FRAMES: u64 = 10;
+
+fn trace(name: &str, filter: TraceFilter) -> Vec<String> {
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("ibm_logo.{}.trace", name));
+    let mut emulator = Emulator::new(CPU::with_seed(Variant::Vip.quirks(), 0), NullVideo, NullAudio, NullInput);
+    emulator.set_clock_mode(ClockMode::Emulated);
+    emulator.load_program(&format!("{}/rom/ibm_logo.ch8", env!("CARGO_MANIFEST_DIR"))).unwrap();
+    let tracer = Tracer::create(path.to_str().unwrap(), filter).unwrap();
+    emulator.cpu_mut().set_tracer(Some(tracer));
+    emulator.run_unthrottled(Some(FRAMES)).unwrap();
+    let mut tracer = emulator.cpu_mut().take_tracer().unwrap();
+    tracer.flush().unwrap();
+    assert_eq!(tracer.cycles(), FRAMES * 15);
+    std::fs::read_to_string(path).unwrap().lines().map(str::to_string).collect()
+}
+
+// The instruction lines of a trace, without its header
+fn steps(lines: &[String]) -> Vec<&String> {
+    lines.iter().filter(|line| !line.starts_with('#')).collect()
+}
+
+fn check(name: &str, filter: TraceFilter, keep: impl Fn(&Step) -> bool) {
+    // Each test writes its own files, as they run in parallel
+    let full = trace(&format!("{}.full", name), TraceFilter::default());
+    let filtered = trace(name, filter);
+    assert_eq!(filtered[..2], full[..2], "the header is always written");
+    let expected: Vec<&String> = steps(&full).into_iter().filter(|line| keep(&Step::parse(line).unwrap())).collect();
+    assert!(!expected.is_empty() && expected.len() < steps(&full).len(), "the filter should drop some lines");
+    assert_eq!(steps(&filtered), expected);
+}
+
+#[test]
+fn filters_by_address_range() {
+    let filter = TraceFilter { ranges: vec![TraceFilter::parse_range("0x20A-0x214").unwrap()], mnemonics: Vec::new() };
+    check("range", filter, |step| (0x20A..=0x214).contains(&step.pc));
+}
+
+#[test]
+fn filters_by_mnemonic() {
+    let filter = TraceFilter { ranges: Vec::new(), mnemonics: TraceFilter::parse_mnemonics("drw, cls").unwrap() };
+    check("mnemonics", filter, |step| step.text.starts_with("DRW ") || step.text == "CLS");
+}
+
+#[test]
+fn combines_ranges_and_mnemonics() {
+    let filter = TraceFilter {
+        ranges: vec![(0x200, 0x20F), (0x220, 0x22F)],
+        mnemonics: TraceFilter::parse_mnemonics("LD").unwrap(),
+    };
+    check("both", filter, |step| {
+        ((0x200..=0x20F).contains(&step.pc) || (0x220..=0x22F).contains(&step.pc)) && step.text.starts_with("LD ")
+    });
+}
+
+#[test]
+fn parses_filter_options() {
+    assert_eq!(TraceFilter::parse_range("0x200"), Some((0x200, 0x200)));
+    assert_eq!(TraceFilter::parse_range("#200-#2FF"), Some((0x200, 0x2FF)));
+    assert_eq!(TraceFilter::parse_range("0x2FF-0x200"), None);
+    assert_eq!(TraceFilter::parse_range("start"), None);
+    assert_eq!(TraceFilter::parse_mnemonics("drw,Ld"), Some(vec!["DRW".to_string(), "LD".to_string()]));
+    assert_eq!(TraceFilter::parse_mnemonics("DRW,"), None);
+}